@@ -37,8 +37,7 @@ pub fn handle_event(evt_type:u32, evt_data: JsValue, state: Rc<RefCell<State>>,
                     let resource = load_gltf(&filepath, None).await?;
                     let mut renderer = renderer.borrow_mut();
 
-                    //maybe upload_gltf should return entity list so it can be mixed in... 
-                    renderer.upload_gltf(&resource, None)?;
+                    let _scene_keys = renderer.upload_gltf(&resource, None)?;
 
                     renderer.set_scene_from_gltf(&resource.gltf);
 
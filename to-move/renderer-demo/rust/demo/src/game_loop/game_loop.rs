@@ -28,7 +28,9 @@ impl GameLoop {
                 let mut renderer = renderer.borrow_mut();
 
                 renderer.clear();
-                renderer.render(Some(interpolation));
+                if let Err(err) = renderer.render(Some(interpolation)) {
+                    log::error!("{}", err);
+                }
             }
         };
 
@@ -2,14 +2,41 @@ use crate::errors::Error;
 use crate::transform::*;
 use crate::renderer::Renderer;
 use shipyard::prelude::*;
-use awsm_web::webgl::{ WebGl2Renderer, ClearBufferMask, BufferData, BufferTarget, BufferUsage, Id};
+use awsm_web::webgl::{ BufferData, BufferTarget, BufferUsage};
 
 pub struct CameraView(pub Matrix4); 
 pub struct CameraProjection(pub Matrix4); 
 
+/// Component on cameras whose projection is kept in sync with the viewport
+/// (e.g. imported from gltf)
+/// Perspective cameras always use the viewport's aspect ratio so they don't get stretched
+#[derive(Clone, Debug, PartialEq)]
+pub enum CameraLens {
+    Perspective {
+        yfov: f64,
+        znear: f64,
+        zfar: Option<f64>,
+    },
+    Orthographic {
+        xmag: f64,
+        ymag: f64,
+        znear: f64,
+        zfar: f64,
+    },
+}
+
+impl CameraLens {
+    pub fn get_projection(&self, aspect_ratio: f64) -> Matrix4 {
+        match self {
+            CameraLens::Perspective { yfov, znear, zfar } => get_perspective_projection(aspect_ratio, *yfov, *znear, *zfar),
+            CameraLens::Orthographic { xmag, ymag, znear, zfar } => get_orthographic_projection(*xmag, *ymag, *znear, *zfar),
+        }
+    }
+}
+
 pub fn get_orthographic_projection(xmag:f64, ymag: f64, znear: f64, zfar: f64) -> Matrix4 {
     let mut projection = Matrix4::default();
-    let mut values = projection.as_mut();
+    let values = projection.as_mut();
 
     values[0] = 1.0/xmag;
     values[5] = 1.0/ymag;
@@ -22,12 +49,12 @@ pub fn get_orthographic_projection(xmag:f64, ymag: f64, znear: f64, zfar: f64) -
 
 pub fn get_perspective_projection(aspect_ratio:f64, yfov: f64, znear: f64, zfar: Option<f64>) -> Matrix4 {
     let mut projection = Matrix4::default();
-    let mut values = projection.as_mut();
+    let values = projection.as_mut();
 
     match zfar {
         None => {
             values[10] = -1.0;
-            values[14] = (-2.0 * znear);
+            values[14] = -2.0 * znear;
         },
         Some(zfar) => { 
             values[10] = (zfar+znear)/(znear-zfar);
@@ -42,55 +69,183 @@ pub fn get_perspective_projection(aspect_ratio:f64, yfov: f64, znear: f64, zfar:
     projection
 }
 
+/// Part of the canvas, as fractions of its size (so it follows resizing)
+/// x and y are from the top-left
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Viewport {
+    pub fn full() -> Self {
+        Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }
+    }
+
+    /// 1.0 if the viewport has no height, rather than dividing by zero
+    pub fn aspect_ratio(&self, canvas_width: u32, canvas_height: u32) -> f64 {
+        let height = self.height * (canvas_height as f64);
+        if height == 0.0 {
+            1.0
+        } else {
+            (self.width * (canvas_width as f64)) / height
+        }
+    }
+
+    /// x, y, width, height in pixels, from the bottom-left like gl.viewport()
+    /// Each edge is rounded to the nearest pixel, so viewports that share an edge
+    /// (e.g. split screen halves) meet without a gap or an overlap
+    pub fn get_pixels(&self, canvas_width: u32, canvas_height: u32) -> (i32, i32, i32, i32) {
+        let canvas_width = canvas_width as f64;
+        let canvas_height = canvas_height as f64;
+
+        let left = (self.x * canvas_width).round();
+        let right = ((self.x + self.width) * canvas_width).round();
+        let top = (self.y * canvas_height).round();
+        let bottom = ((self.y + self.height) * canvas_height).round();
+
+        (left as i32, (canvas_height - bottom) as i32, (right - left) as i32, (bottom - top) as i32)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraViewport {
+    pub camera: EntityId,
+    pub viewport: Viewport,
+}
+
+/// Unique in the world: the cameras that are rendered each frame
+/// They're drawn in order, each into its own viewport
+/// so later ones are drawn over earlier ones (e.g. a minimap over the main view)
+///
+/// If it's empty, the first found camera is rendered to the whole canvas
+#[derive(Clone, Debug, Default)]
+pub struct ActiveCameras(pub Vec<CameraViewport>);
+
 impl Renderer {
-    /// gets the first found camera node 
-    pub fn get_camera_node(&self) -> Option<Key> {
+    /// gets the active camera node (the first one if there are several)
+    /// or the first found camera node if there isn't one
+    pub fn get_camera_node(&self) -> Option<EntityId> {
+        self.get_camera_viewports()
+            .first()
+            .map(|camera_viewport| camera_viewport.camera)
+    }
+
+    /// The cameras that will be rendered, skipping any that aren't cameras (anymore)
+    pub fn get_camera_viewports(&self) -> Vec<CameraViewport> {
+        get_camera_viewports(&self.world.borrow())
+    }
+
+    /// Renders only this camera, to the whole canvas
+    /// None goes back to using the first found camera node
+    pub fn set_active_camera(&mut self, node: Option<EntityId>) {
+        let camera_viewports = match node {
+            Some(camera) => vec![CameraViewport { camera, viewport: Viewport::full() }],
+            None => Vec::new()
+        };
+        self.set_camera_viewports(camera_viewports);
+    }
+
+    pub fn get_active_camera(&self) -> Option<EntityId> {
         let world = self.world.borrow();
-        world.run::<(&CameraView, &CameraProjection), _, _>(|(views, projs)| {
-            (&views, &projs).iter().with_id().map(|(id, _, _)| id).next()
+        world.run::<Unique<&ActiveCameras>, _, _>(|active_cameras| {
+            active_cameras.0.first().map(|camera_viewport| camera_viewport.camera)
         })
     }
+
+    /// Replaces all the active cameras (e.g. for split screen)
+    pub fn set_camera_viewports(&mut self, camera_viewports: Vec<CameraViewport>) {
+        let world = self.world.borrow_mut();
+        world.run::<Unique<&mut ActiveCameras>, _, _>(|mut active_cameras| {
+            active_cameras.0 = camera_viewports;
+        });
+    }
+
+    /// Also renders this camera, over the ones already added (e.g. picture-in-picture)
+    /// The same camera can be added more than once, to render it into several viewports
+    pub fn add_camera_viewport(&mut self, camera: EntityId, viewport: Viewport) {
+        add_camera_viewport(&self.world.borrow(), camera, viewport);
+    }
+
+    /// Stops rendering the camera, in any of its viewports
+    /// The others keep their order
+    pub fn remove_camera_viewport(&mut self, camera: EntityId) {
+        remove_camera_viewport(&self.world.borrow(), camera);
+    }
+
+    /// Of the whole canvas, 1.0 if it has no height
+    pub fn aspect_ratio(&self) -> f64 {
+        Viewport::full().aspect_ratio(self.width, self.height)
+    }
+
+    /// The projection is updated now and whenever the renderer is resized
+    pub fn set_camera_lens(&mut self, node: EntityId, lens: CameraLens) {
+        {
+            let world = self.world.borrow_mut();
+            world.run::<(EntitiesMut, &mut CameraLens), _, _>(|(entities, mut lenses)| {
+                match (&mut lenses).get(node).ok() {
+                    Some(existing) => *existing = lens,
+                    None => entities.add_component(&mut lenses, lens, node)
+                }
+            });
+        }
+
+        self.update_camera_lenses();
+    }
+
+    /// Recalculates the projection of every camera with a lens, for the current aspect ratio
+    pub fn update_camera_lenses(&mut self) {
+        let aspect_ratio = self.aspect_ratio();
+        let world = self.world.borrow_mut();
+
+        world.run::<(&CameraLens, &mut CameraProjection), _, _>(|(lenses, mut projs)| {
+            for (lens, proj) in (&lenses, &mut projs).iter().into_iter() {
+                proj.0 = lens.get_projection(aspect_ratio);
+            }
+        });
+    }
     /// if no node is provided then the first camera node will be used 
-    pub fn update_camera_projection(&mut self, node: Option<Key>, projection:&[f64]) {
+    pub fn update_camera_projection(&mut self, node: Option<EntityId>, projection:&[f64]) {
         let node = if node.is_none() { self.get_camera_node() } else { node };
         if let Some(node) = node {
             let world = self.world.borrow_mut();
-            let proj = world.run::<&mut CameraProjection, _, _>(|mut projs| {
-                if let Some(proj) = (&mut projs).get(node).iter_mut().next() {
+            world.run::<&mut CameraProjection, _, _>(|mut projs| {
+                if let Ok(proj) = (&mut projs).get(node) {
                     proj.0.as_mut().copy_from_slice(projection);
                 } 
             });
         }
     }
     /// if no node is provided then the first camera node will be used 
-    pub fn update_camera_view(&mut self, node: Option<Key>) {
+    /// The view is left as it was if the node's world transform can't be inverted (e.g. it's scaled to 0)
+    /// and that's returned as NativeError::InvertMatrix
+    pub fn update_camera_view(&mut self, node: Option<EntityId>) -> Result<(), Error> {
         let node = if node.is_none() { self.get_camera_node() } else { node };
-        if let Some(node) = node {
-            let world = self.world.borrow_mut();
-            world.run::<(&mut CameraView, &LocalTransform), _, _>(|(mut views, local_mats)| {
-                if let Some((view, local_mat)) = (&mut views, &local_mats).get(node).iter_mut().next() {
-                    let view = &mut view.0;
-                    let local_mat = &local_mat.0;
-                    view.copy_from_slice(local_mat.as_ref());
-                    view.invert_mut().unwrap();
-                } 
-            });
+        match node {
+            Some(node) => update_camera_view(&self.world.borrow(), node),
+            None => Ok(())
         }
     }
 
     /// if no node is provided then the first camera node will be used 
-    pub(crate) fn update_camera_ubo(&mut self, node:Option<Key>) {
+    /// cameras with a lens get their projection for the aspect ratio (i.e. of the viewport they're rendered to)
+    pub(crate) fn update_camera_ubo(&mut self, node:Option<EntityId>, aspect_ratio: Option<f64>) -> Result<(), Error> {
         let node = if node.is_none() { self.get_camera_node() } else { node };
         if let Some(node) = node {
             let world = self.world.borrow_mut();
             let webgl = self.webgl.borrow_mut();
 
-            world.run::<(&CameraView, &CameraProjection), _, _>(|(views, projs)| {
-                if let Some((view, proj)) = (&views, &projs).get(node).iter_mut().next() {
+            world.run::<(&CameraView, &CameraProjection, &CameraLens), _, _>(|(views, projs, lenses)| {
+                if let Ok((view, proj)) = (&views, &projs).get(node) {
                     let view = &view.0;
-                    let projection = &proj.0;
+                    let lens_projection = aspect_ratio.and_then(|aspect_ratio| {
+                        (&lenses).get(node).ok().map(|lens| lens.get_projection(aspect_ratio))
+                    });
+                    let projection = lens_projection.as_ref().unwrap_or(&proj.0);
                     
-                    let camera = vec![view.to_vec_f32(), projection.to_vec_f32()].concat();
+                    let camera = [view.to_vec_f32(), projection.to_vec_f32()].concat();
                     webgl.upload_buffer(
                         self.camera_buffer_id,
                         BufferData::new(
@@ -98,9 +253,256 @@ impl Renderer {
                             BufferTarget::UniformBuffer,
                             BufferUsage::DynamicDraw,
                         ),
-                    ).unwrap();
+                    )?;
                 } 
-            });
+                Ok(())
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn get_camera_viewports(world:&World) -> Vec<CameraViewport> {
+    world.run::<(Unique<&ActiveCameras>, &CameraView, &CameraProjection), _, _>(|(active_cameras, views, projs)| {
+        let camera_viewports:Vec<CameraViewport> = active_cameras.0
+            .iter()
+            .filter(|camera_viewport| (&views, &projs).get(camera_viewport.camera).is_ok())
+            .copied()
+            .collect();
+
+        if camera_viewports.is_empty() {
+            (&views, &projs)
+                .iter()
+                .with_id()
+                .into_iter()
+                .map(|(id, _)| CameraViewport { camera: id, viewport: Viewport::full() })
+                .take(1)
+                .collect()
+        } else {
+            camera_viewports
+        }
+    })
+}
+
+fn add_camera_viewport(world:&World, camera: EntityId, viewport: Viewport) {
+    world.run::<Unique<&mut ActiveCameras>, _, _>(|mut active_cameras| {
+        active_cameras.0.push(CameraViewport { camera, viewport });
+    });
+}
+
+fn remove_camera_viewport(world:&World, camera: EntityId) {
+    world.run::<Unique<&mut ActiveCameras>, _, _>(|mut active_cameras| {
+        active_cameras.0.retain(|camera_viewport| camera_viewport.camera != camera);
+    });
+}
+
+/// Sets the view to the inverse of the camera's world transform
+/// Does nothing if the node isn't a camera
+pub fn update_camera_view(world:&World, node:EntityId) -> Result<(), Error> {
+    world.run::<(&mut CameraView, &WorldTransform), _, _>(|(mut views, world_mats)| {
+        if let Ok((view, world_mat)) = (&mut views, &world_mats).get(node) {
+            view.0 = Matrix4::invert_clone(&world_mat.0)?;
         }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::NativeError;
+    use crate::nodes::{add_node, update_transforms, Node, NodeData};
+
+    fn add_camera(world:&mut World, parent:Option<EntityId>, x:f64, y:f64, z:f64) -> EntityId {
+        let projection = get_perspective_projection(1.0, 1.0, 0.1, None);
+        add_node(world, NodeData::Camera(projection), parent, Some(Vector3::new(x, y, z)), None, None).unwrap()
+    }
+
+    fn get_view(world:&World, camera:EntityId) -> Vec<f64> {
+        world.run::<&CameraView, _, _>(|views| (&views).get(camera).unwrap().0.as_ref().to_vec())
+    }
+
+    #[test]
+    fn view_is_inverse_world_transform() {
+        let mut world = World::default();
+        let parent = add_node(&mut world, NodeData::Empty, None, Some(Vector3::new(1.0, 0.0, 0.0)), None, None).unwrap();
+        let camera = add_camera(&mut world, Some(parent), 0.0, 2.0, 5.0);
+        update_transforms(&world);
+
+        update_camera_view(&world, camera).unwrap();
+
+        let view = get_view(&world, camera);
+        assert_eq!(&view[12..15], &[-1.0, -2.0, -5.0]);
+    }
+
+    #[test]
+    fn degenerate_transform_is_an_error() {
+        let mut world = World::default();
+        let camera = add_camera(&mut world, None, 0.0, 0.0, 5.0);
+        update_transforms(&world);
+        update_camera_view(&world, camera).unwrap();
+        let before = get_view(&world, camera);
+
+        world.run::<(&mut Node, &mut Scale), _, _>(|(mut nodes, mut scales)| {
+            (&mut scales).get(camera).unwrap().0 = Vector3::new(0.0, 0.0, 0.0);
+            (&mut nodes).get(camera).unwrap().dirty = true;
+        });
+        update_transforms(&world);
+
+        let result = update_camera_view(&world, camera);
+        assert!(matches!(result, Err(Error::Native(NativeError::InvertMatrix))));
+        //left as it was
+        assert_eq!(get_view(&world, camera), before);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn view_of_non_camera() {
+        let mut world = World::default();
+        let node = add_node(&mut world, NodeData::Empty, None, None, None, None).unwrap();
+
+        assert!(update_camera_view(&world, node).is_ok());
+    }
+
+    #[test]
+    fn perspective_projection() {
+        let projection = get_perspective_projection(2.0, std::f64::consts::FRAC_PI_2, 1.0, Some(3.0));
+        let values:&[f64] = projection.as_ref();
+
+        //tan(45 degrees) is 1
+        assert!((values[0] - 0.5).abs() < 1e-9);
+        assert!((values[5] - 1.0).abs() < 1e-9);
+        assert_eq!(values[10], -2.0);
+        assert_eq!(values[11], -1.0);
+        assert_eq!(values[14], -3.0);
+
+        //infinite far plane
+        let projection = get_perspective_projection(1.0, std::f64::consts::FRAC_PI_2, 0.5, None);
+        let values:&[f64] = projection.as_ref();
+        assert_eq!((values[10], values[14]), (-1.0, -1.0));
+    }
+
+    #[test]
+    fn orthographic_projection() {
+        let projection = get_orthographic_projection(2.0, 4.0, 1.0, 3.0);
+        let values:&[f64] = projection.as_ref();
+
+        assert_eq!((values[0], values[5], values[10], values[14], values[15]), (0.5, 0.25, -1.0, -2.0, 1.0));
+    }
+
+    fn get_world() -> World {
+        let mut world = World::default();
+        crate::components::register_components(&mut world);
+        world
+    }
+
+    fn get_viewport(x:f64, y:f64, width:f64, height:f64) -> Viewport {
+        Viewport { x, y, width, height }
+    }
+
+    fn get_cameras(world:&World) -> Vec<EntityId> {
+        get_camera_viewports(world).iter().map(|camera_viewport| camera_viewport.camera).collect()
+    }
+
+    #[test]
+    fn full_viewport_pixels() {
+        assert_eq!(Viewport::full().get_pixels(800, 600), (0, 0, 800, 600));
+    }
+
+    #[test]
+    fn pixels_are_from_the_bottom_left() {
+        //top-right quarter
+        assert_eq!(get_viewport(0.5, 0.0, 0.5, 0.5).get_pixels(800, 600), (400, 300, 400, 300));
+        //bottom-left quarter
+        assert_eq!(get_viewport(0.0, 0.5, 0.5, 0.5).get_pixels(800, 600), (0, 0, 400, 300));
+    }
+
+    #[test]
+    fn pixels_round_to_nearest() {
+        //0.25 * 101 = 25.25, 0.75 * 101 = 75.75
+        assert_eq!(get_viewport(0.25, 0.25, 0.5, 0.5).get_pixels(101, 101), (25, 25, 51, 51));
+        //a third of 100 is 33.33
+        assert_eq!(get_viewport(0.0, 0.0, 1.0 / 3.0, 1.0).get_pixels(100, 100), (0, 0, 33, 100));
+    }
+
+    #[test]
+    fn shared_edges_dont_overlap() {
+        //odd sizes, so the half-way point is on a half pixel
+        let (canvas_width, canvas_height) = (101, 75);
+        let left = get_viewport(0.0, 0.0, 0.5, 1.0).get_pixels(canvas_width, canvas_height);
+        let right = get_viewport(0.5, 0.0, 0.5, 1.0).get_pixels(canvas_width, canvas_height);
+        assert_eq!(left.0 + left.2, right.0);
+        assert_eq!(right.0 + right.2, canvas_width as i32);
+
+        let top = get_viewport(0.0, 0.0, 1.0, 0.5).get_pixels(canvas_width, canvas_height);
+        let bottom = get_viewport(0.0, 0.5, 1.0, 0.5).get_pixels(canvas_width, canvas_height);
+        assert_eq!(bottom.1, 0);
+        assert_eq!(bottom.1 + bottom.3, top.1);
+        assert_eq!(top.1 + top.3, canvas_height as i32);
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        assert_eq!(Viewport::full().aspect_ratio(800, 400), 2.0);
+        assert_eq!(get_viewport(0.0, 0.0, 0.5, 1.0).aspect_ratio(800, 400), 1.0);
+    }
+
+    #[test]
+    fn zero_height_aspect_ratio() {
+        assert_eq!(Viewport::full().aspect_ratio(800, 0), 1.0);
+        assert_eq!(get_viewport(0.0, 0.0, 1.0, 0.0).aspect_ratio(800, 600), 1.0);
+        assert_eq!(Viewport::full().aspect_ratio(0, 0), 1.0);
+    }
+
+    #[test]
+    fn viewports_keep_their_order() {
+        let mut world = get_world();
+        let main = add_camera(&mut world, None, 0.0, 0.0, 0.0);
+        let minimap = add_camera(&mut world, None, 0.0, 10.0, 0.0);
+        let mirror = add_camera(&mut world, None, 0.0, 0.0, 1.0);
+
+        add_camera_viewport(&world, main, Viewport::full());
+        add_camera_viewport(&world, minimap, get_viewport(0.75, 0.0, 0.25, 0.25));
+        add_camera_viewport(&world, mirror, get_viewport(0.0, 0.0, 0.25, 0.25));
+        assert_eq!(get_cameras(&world), vec![main, minimap, mirror]);
+
+        remove_camera_viewport(&world, minimap);
+        assert_eq!(get_cameras(&world), vec![main, mirror]);
+
+        //added again, so now it's drawn last
+        add_camera_viewport(&world, minimap, get_viewport(0.75, 0.0, 0.25, 0.25));
+        assert_eq!(get_cameras(&world), vec![main, mirror, minimap]);
+        assert_eq!(get_camera_viewports(&world)[2].viewport, get_viewport(0.75, 0.0, 0.25, 0.25));
+    }
+
+    #[test]
+    fn same_camera_in_several_viewports() {
+        let mut world = get_world();
+        let main = add_camera(&mut world, None, 0.0, 0.0, 0.0);
+        let other = add_camera(&mut world, None, 0.0, 0.0, 1.0);
+
+        add_camera_viewport(&world, main, get_viewport(0.0, 0.0, 0.5, 1.0));
+        add_camera_viewport(&world, other, Viewport::full());
+        add_camera_viewport(&world, main, get_viewport(0.5, 0.0, 0.5, 1.0));
+        assert_eq!(get_cameras(&world), vec![main, other, main]);
+
+        //removed from all of them
+        remove_camera_viewport(&world, main);
+        assert_eq!(get_cameras(&world), vec![other]);
+    }
+
+    #[test]
+    fn falls_back_to_first_camera() {
+        let mut world = get_world();
+        assert!(get_camera_viewports(&world).is_empty());
+
+        let camera = add_camera(&mut world, None, 0.0, 0.0, 0.0);
+        let not_a_camera = add_node(&mut world, NodeData::Empty, None, None, None, None).unwrap();
+
+        assert_eq!(get_camera_viewports(&world), vec![CameraViewport { camera, viewport: Viewport::full() }]);
+
+        //viewports of nodes that aren't cameras are skipped, which leaves none
+        add_camera_viewport(&world, not_a_camera, get_viewport(0.0, 0.0, 0.5, 0.5));
+        assert_eq!(get_camera_viewports(&world), vec![CameraViewport { camera, viewport: Viewport::full() }]);
+    }
+}
@@ -2,16 +2,11 @@ use shipyard::prelude::*;
 pub use crate::primitives::Primitive;
 pub use crate::transform::*;
 pub use crate::camera::*;
-pub use crate::nodes::{Node};
+pub use crate::nodes::{Node, Name, Tags};
+pub use crate::materials::{CustomMaterial, PbrMaterial};
+pub use crate::lights::Light;
 
+/// Storages are created the first time they're borrowed, only uniques have to be added up front
 pub fn register_components(world:&mut World) {
-    world.register::<Node>();
-    world.register::<Primitive>();
-    world.register::<CameraView>();
-    world.register::<CameraProjection>();
-    world.register::<Translation>();
-    world.register::<Rotation>();
-    world.register::<Scale>();
-    world.register::<LocalTransform>();
-    world.register::<WorldTransform>();
+    world.add_unique(ActiveCameras::default());
 }
\ No newline at end of file
@@ -1,7 +1,8 @@
 use std::fmt;
 use wasm_bindgen::prelude::JsValue;
-use awsm_web::errors::Error as AwsmWebError;
+use awsm_web::errors::{Error as AwsmWebError, NativeError as AwsmWebNativeError};
 
+#[derive(Clone)]
 pub enum Error {
     Empty,
     String(String),
@@ -9,19 +10,53 @@ pub enum Error {
     Native(NativeError),
 }
 
+#[derive(Clone)]
 pub enum NativeError {
     Internal,
     GltfLoader,
+    Cancelled,
+    FetchMissing(String),
+    DataUri(String),
+    DataUriMimeType(String),
     SceneMissing,
     AccessorView,
+    AccessorSparseIndex(usize, usize),
+    AccessorBounds(usize, usize, usize),
+    AccessorDataType(&'static str),
+    AccessorDimensions(&'static str),
     Wip,
     InvertMatrix,
     AttributeDimSize(String, u8, usize),
+    AttributeCount(String, usize, usize),
+    AttributePositionMissing(usize),
     NodeMissing(usize),
+    ParentMissing,
+    ParentCycle,
+    NodeKeyMissing,
+    ShaderInclude(String),
+    ShaderIncludeMissing(String),
+    ShaderIncludeCycle(String),
+    ShaderCompile(String),
+    MaterialMissing,
+    MaterialParam(String),
+    TextureSize(usize, usize),
+    TextureMissing,
+}
+
+/// Uniforms, samplers and blocks that the compiler optimized away have no location
+/// That's expected for shaders that don't use everything they declare, so it's not an error
+/// Anything else (e.g. the wrong type or size) still is
+pub(crate) fn allow_missing_uniform(result:Result<(), AwsmWebError>) -> Result<(), Error> {
+    match result {
+        Err(AwsmWebError::Native(AwsmWebNativeError::UniformLocation(_)))
+        | Err(AwsmWebError::Native(AwsmWebNativeError::MissingTextureSampler(_)))
+        | Err(AwsmWebError::Native(AwsmWebNativeError::UniformBufferMissing(_))) => Ok(()),
+        result => result.map_err(Error::from)
+    }
 }
 
 impl Error {
-    pub fn to_js(self: &Self) -> JsValue {
+    pub fn to_js(&self) -> JsValue {
         match self {
             Error::Empty => JsValue::null(),
             Error::String(s) => JsValue::from_str(&s[..]),
@@ -33,27 +68,21 @@ impl Error {
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            Error::Empty => write!(f, "empty error"),
-            _ => write!(
-                f,
-                "{}",
-                self.to_js()
-                    .as_string()
-                    .unwrap_or("unknown error".to_string())
-            ),
-        }
+        fmt::Display::fmt(self, f)
     }
 }
 
+//Only Js errors go through JsValue, so that the rest can be formatted off the web too
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self {
             Error::Empty => write!(f, "empty error"),
-            _ => write!(
+            Error::String(s) => write!(f, "{}", s),
+            Error::Native(err) => write!(f, "{}", err),
+            Error::Js(jval) => write!(
                 f,
                 "{}",
-                self.to_js()
+                jval
                     .as_string()
                     .unwrap_or("unknown error".to_string())
             ),
@@ -62,24 +91,64 @@ impl fmt::Display for Error {
 }
 
 impl NativeError {
-    pub fn default_str(self: &Self) -> &'static str {
+    pub fn default_str(&self) -> &'static str {
         match self {
             NativeError::Internal => "internal error",
             NativeError::GltfLoader => "unable to load gltf",
+            NativeError::Cancelled => "loading was cancelled",
+            NativeError::FetchMissing(_) => "missing file",
+            NativeError::DataUri(_) => "malformed data uri",
+            NativeError::DataUriMimeType(_) => "unsupported data uri media type",
             NativeError::SceneMissing=> "no such scene",
             NativeError::AccessorView => "non-sparse accessor must have a buffer view",
+            NativeError::AccessorSparseIndex(_, _) => "sparse accessor index out of range",
+            NativeError::AccessorBounds(_, _, _) => "accessor data out of buffer bounds",
+            NativeError::AccessorDataType(_) => "wrong accessor data type",
+            NativeError::AccessorDimensions(_) => "wrong accessor dimensions",
             NativeError::Wip => "Work In Progress",
             NativeError::InvertMatrix => "Unable to invert matrix",
             NativeError::AttributeDimSize(_, _, _) => "wrong attribute dimension size",
+            NativeError::AttributeCount(_, _, _) => "attribute count doesn't match the vertex count",
+            NativeError::AttributePositionMissing(_) => "primitive has no POSITION attribute",
             NativeError::NodeMissing(_) => "missing node",
+            NativeError::ParentMissing => "parent node doesn't exist",
+            NativeError::ParentCycle => "node can't be moved under itself",
+            NativeError::NodeKeyMissing => "node doesn't exist",
+            NativeError::ShaderInclude(_) => "malformed shader include",
+            NativeError::ShaderIncludeMissing(_) => "missing shader chunk",
+            NativeError::ShaderIncludeCycle(_) => "shader chunks include each other",
+            NativeError::ShaderCompile(_) => "unable to compile shader",
+            NativeError::MaterialMissing => "material isn't registered",
+            NativeError::MaterialParam(_) => "material param isn't declared with this type",
+            NativeError::TextureSize(_, _) => "texture data doesn't match its size",
+            NativeError::TextureMissing => "texture isn't managed or was already deleted",
         }
     }
-    pub fn to_string(self: &Self) -> String {
-        match self {
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            NativeError::DataUri(reason) => format!("malformed data uri: {}", reason),
+            NativeError::DataUriMimeType(mime_type) => format!("unsupported data uri media type: {}", mime_type),
+            NativeError::FetchMissing(url) => format!("missing file: {}", url),
             NativeError::NodeMissing(index) => format!("missing node: {}", index),
+            NativeError::AccessorSparseIndex(accessor, index) => format!("sparse index {} out of range for accessor {}", index, accessor),
+            NativeError::AccessorBounds(start, end, len) => format!("accessor data {}..{} out of bounds for buffer of length {}", start, end, len),
+            NativeError::AccessorDataType(target) => format!("accessor data type can't be read as {}", target),
+            NativeError::AccessorDimensions(target) => format!("accessor dimensions don't match {}", target),
             NativeError::AttributeDimSize(name, expected, got) => format!("wrong size for attribute {}: expected {} got {}", name, expected, got),
+            NativeError::AttributeCount(name, expected, got) => format!("attribute {} has {} elements but POSITION has {}", name, got, expected),
+            NativeError::AttributePositionMissing(index) => format!("primitive {} has no POSITION attribute", index),
+            NativeError::ShaderInclude(line) => format!("malformed shader include: {}", line),
+            NativeError::ShaderIncludeMissing(name) => format!("missing shader chunk: {}", name),
+            NativeError::ShaderIncludeCycle(chain) => format!("shader chunks include each other: {}", chain),
+            NativeError::ShaderCompile(log) => format!("unable to compile shader:\n{}", log),
+            NativeError::MaterialParam(name) => format!("material param isn't declared with this type: {}", name),
+            NativeError::TextureSize(expected, got) => format!("texture data should be {} bytes but is {}", expected, got),
             _ => self.default_str().to_string(),
-        }
+        };
+        write!(f, "{}", s)
     }
 }
 
@@ -103,7 +172,12 @@ impl From<JsValue> for Error {
 
 impl From<AwsmWebError> for Error {
     fn from(err: AwsmWebError) -> Self {
-        Error::Js(err.to_js())
+        match err {
+            AwsmWebError::Empty => Error::Empty,
+            AwsmWebError::String(s) => Error::String(s),
+            AwsmWebError::Js(jval) => Error::Js(jval),
+            AwsmWebError::Native(err) => Error::String(err.to_string()),
+        }
     }
 }
 
@@ -140,6 +214,6 @@ impl From<Error> for gltf::Error {
     fn from(err:Error) -> Self {
         //gltf:Error doesn't seem to implement a From for string
         //but io:Other isn't too confusing and actually fits the usual case
-        gltf::Error::from(std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))
+        gltf::Error::from(std::io::Error::other(err.to_string()))
     }
 }
\ No newline at end of file
@@ -1,20 +1,51 @@
 use gltf::accessor::{DataType, Dimensions};
+use gltf::accessor::sparse::IndexType;
+use crate::errors::{Error, NativeError};
+use awsm_web::webgl::AttributeOptions;
+use std::borrow::Cow;
 
 pub struct AccessorInfo {
     pub dim_size:usize,
-    pub data_size:u8,
-    pub webgl_data_type:awsm_web::webgl::DataType
+    pub webgl_data_type:awsm_web::webgl::DataType,
+    pub normalized:bool,
 }
 
 impl AccessorInfo {
     pub fn new(accessor:&gltf::accessor::Accessor) -> Self {
         Self{
             dim_size: get_accessor_dim_size(accessor.dimensions()),
-            data_size: get_accessor_data_size(accessor.data_type()),
             webgl_data_type: get_accessor_webgl_data_type(accessor.data_type()),
+            normalized: accessor.normalized(),
         }
     }
 }
+
+/// The vertex attribute layout of the accessor within its uploaded buffer
+/// Buffer views are uploaded whole, so the accessor's offset and the view's stride apply
+/// Sparse accessors are uploaded on their own and tightly packed
+/// (a stride of 0 means tightly packed)
+/// Matrices are rejected, since they would take more than one attribute slot
+pub fn get_attribute_options(accessor:&gltf::accessor::Accessor, info:&AccessorInfo) -> Result<AttributeOptions, Error> {
+    match accessor.dimensions() {
+        Dimensions::Mat2 | Dimensions::Mat3 | Dimensions::Mat4 => {
+            return Err(NativeError::AccessorDimensions("vertex attribute").into());
+        },
+        _ => {}
+    }
+
+    let (stride, offset) = match (accessor.sparse(), accessor.view()) {
+        (None, Some(view)) => (view.stride().unwrap_or(0), accessor.offset()),
+        _ => (0, 0)
+    };
+
+    Ok(AttributeOptions {
+        size: info.dim_size as u8,
+        data_type: info.webgl_data_type,
+        normalized: info.normalized,
+        stride: stride as u8,
+        offset: offset as u64,
+    })
+}
             
 fn get_accessor_dim_size(type_:gltf::accessor::Dimensions) -> usize {
     match type_ {
@@ -47,12 +78,11 @@ fn get_accessor_webgl_data_type(gltf_type:DataType) -> awsm_web::webgl::DataType
     }
 }
 
-/*
-fn element_byte_size(accessor:&gltf::accessor::Accessor) -> usize {
-    dim_size(accessor.dimensions()) * component_size(accessor.data_type())
+pub fn element_byte_size(accessor:&gltf::accessor::Accessor) -> usize {
+    get_accessor_dim_size(accessor.dimensions()) * (get_accessor_data_size(accessor.data_type()) as usize)
 }
 
-fn get_byte_length(accessor:&gltf::accessor::Accessor) -> usize {
+pub fn get_byte_length(accessor:&gltf::accessor::Accessor) -> usize {
     accessor.count() * element_byte_size(accessor) 
 }
 
@@ -61,104 +91,372 @@ fn get_byte_offset(view:&gltf::buffer::View, accessor:&gltf::accessor::Accessor)
     view.offset() + accessor.offset()
 }
 
-fn get_byte_stride_len(view:&gltf::buffer::View, accessor:&gltf::accessor::Accessor) -> usize {
-    match view.stride() {
-        None => 0,
-        Some(stride) => {
-            stride * element_byte_size(accessor) 
+/// Gets the accessor's data as a tightly-packed byte slice
+/// If the data can be used as-is, it's borrowed straight from the buffer
+/// Otherwise (strided or sparse) a new Vec is created:
+/// 1. base data is copied from the buffer view, or zero-filled if there is no view
+/// 2. sparse values are written over the base data at the sparse indices
+pub fn get_accessor_data<'a> (accessor:&gltf::accessor::Accessor, buffers:&'a [Vec<u8>]) -> Result<Cow<'a, [u8]>, Error> {
+    let element_size = element_byte_size(accessor);
+    let byte_len = get_byte_length(accessor);
+
+    let mut data:Cow<'a, [u8]> = match accessor.view() {
+        Some(view) => {
+            let byte_offset = get_byte_offset(&view, accessor);
+            let full_buffer_data = &buffers[view.buffer().index()];
+
+            match view.stride() {
+                Some(stride) if stride > element_size => {
+                    let mut data = Vec::with_capacity(byte_len);
+                    for index in 0..accessor.count() {
+                        let start = byte_offset + (index * stride);
+                        let end = start + element_size;
+                        data.extend_from_slice(get_slice(full_buffer_data, start, end)?);
+                    }
+                    Cow::Owned(data)
+                },
+                _ => {
+                    Cow::Borrowed(get_slice(full_buffer_data, byte_offset, byte_offset + byte_len)?)
+                }
+            }
+        },
+        None => {
+            if accessor.sparse().is_none() {
+                return Err(NativeError::AccessorView.into());
+            }
+            Cow::Owned(vec![0;byte_len])
+        }
+    };
+
+    if let Some(sparse) = accessor.sparse() {
+        let info = SparseAccessorInfo::new(&sparse, element_size);
+        let indices = info.get_indices(buffers)?;
+        let values = info.get_values(buffers)?;
+        let data = data.to_mut();
+
+        for (n, index) in indices.into_iter().enumerate() {
+            if index >= accessor.count() {
+                return Err(NativeError::AccessorSparseIndex(accessor.index(), index).into());
+            }
+            let dest = index * element_size;
+            let src = n * element_size;
+            data[dest..dest + element_size].copy_from_slice(&values[src..src + element_size]);
         }
     }
-}
 
-#[derive(Debug)]
-struct AccessorInfo {
-    base: BaseAccessorInfo,
-    sparse: Option<SparseAccessorInfo>
+    Ok(data)
 }
 
-#[derive(Debug)]
 struct SparseAccessorInfo {
-    indices: BaseAccessorInfo,
-    values: BaseAccessorInfo
+    count: usize,
+    element_size: usize,
+    indices_offset: usize,
+    indices_buffer: usize,
+    index_type: IndexType,
+    values_offset: usize,
+    values_buffer: usize,
+}
+
+impl SparseAccessorInfo {
+    fn new(sparse:&gltf::accessor::sparse::Sparse, element_size: usize) -> Self {
+        let indices = sparse.indices();
+        let values = sparse.values();
+        let indices_view = indices.view();
+        let values_view = values.view();
+
+        Self {
+            count: sparse.count() as usize,
+            element_size,
+            indices_offset: indices_view.offset() + (indices.offset() as usize),
+            indices_buffer: indices_view.buffer().index(),
+            index_type: indices.index_type(),
+            values_offset: values_view.offset() + (values.offset() as usize),
+            values_buffer: values_view.buffer().index(),
+        }
+    }
+
+    fn get_indices(&self, buffers:&[Vec<u8>]) -> Result<Vec<usize>, Error> {
+        let index_size = match self.index_type {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        };
+        let start = self.indices_offset;
+        let end = start + (self.count * index_size);
+        let data = get_slice(&buffers[self.indices_buffer], start, end)?;
+
+        Ok(data.chunks_exact(index_size).map(|bytes| {
+            match self.index_type {
+                IndexType::U8 => bytes[0] as usize,
+                IndexType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+                IndexType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize,
+            }
+        }).collect())
+    }
+
+    fn get_values<'a>(&self, buffers:&'a [Vec<u8>]) -> Result<&'a [u8], Error> {
+        let start = self.values_offset;
+        let end = start + (self.count * self.element_size);
+        get_slice(&buffers[self.values_buffer], start, end)
+    }
 }
 
-#[derive(Debug)]
-struct BaseAccessorInfo {
-    len: usize,
-    offset: usize,
-    component_type: DataType,
-    dim_type: Dimensions,
-    buffer_id: Id,
+fn get_slice(data:&[u8], start: usize, end: usize) -> Result<&[u8], Error> {
+    data.get(start..end).ok_or_else(|| NativeError::AccessorBounds(start, end, data.len()).into())
 }
 
-//TODO - implement getting typed data
-// https://github.com/dakom/pure3d-typescript/blob/master/src/lib/internal/gltf/gltf-parse/Gltf-Parse-Data-Typed.ts
-// https://users.rust-lang.org/t/return-new-vec-or-slice/34542
-fn get_accessor_data<'a> (accessor:&gltf::accessor::Accessor, buffers:&'a Vec<Vec<u8>>) -> Cow<'a, [u8]> {
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    //TODO - remove the temp Some wrapper
-    //https://github.com/gltf-rs/gltf/issues/266
-    match Some(accessor.view()) {
-        Some(view) => {
-            let byte_offset = get_byte_offset(&view, accessor);
-            let byte_len = get_byte_length(accessor);
-            let byte_end = byte_offset + byte_len;
-            let full_buffer_data = &buffers[view.buffer().index()];
+    //a single buffer and whatever views/accessors the test needs
+    fn get_document(buffer_len:usize, views:&str, accessors:&str) -> gltf::Document {
+        let json = format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": {}, "uri": "test.bin" }}],
+            "bufferViews": [{}],
+            "accessors": [{}]
+        }}"#, buffer_len, views, accessors);
 
-            //info!("target length {} start {} end {}", full_buffer_data.len(), byte_offset, byte_end);
+        gltf::Gltf::from_slice(json.as_bytes()).unwrap().document
+    }
 
-            Cow::Borrowed(&full_buffer_data[byte_offset..byte_end])
-        },
-        None => {
-            let n_values = accessor.count() * dim_size(accessor.dimensions());
+    fn u16_bytes(values:&[u16]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    fn u32_bytes(values:&[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    fn read(document:&gltf::Document, buffer:Vec<u8>) -> Result<Vec<u8>, Error> {
+        let buffers = vec![buffer];
+        let accessor = document.accessors().next().unwrap();
+        get_accessor_data(&accessor, &buffers).map(|data| data.into_owned())
+    }
+
+    #[test]
+    fn tightly_packed_view_is_borrowed() {
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 8 }"#,
+            r#"{ "bufferView": 0, "byteOffset": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }"#
+        );
+        let buffers = vec![u16_bytes(&[9, 1, 2, 3])];
+        let accessor = document.accessors().next().unwrap();
+
+        let data = get_accessor_data(&accessor, &buffers).unwrap();
+
+        assert!(matches!(data, Cow::Borrowed(_)));
+        assert_eq!(data.as_ref(), u16_bytes(&[1, 2, 3]).as_slice());
+    }
+
+    #[test]
+    fn strided_view_is_packed() {
+        //interleaved u16 pairs, the accessor reads the second of each
+        let document = get_document(12,
+            r#"{ "buffer": 0, "byteLength": 12, "byteStride": 4 }"#,
+            r#"{ "bufferView": 0, "byteOffset": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }"#
+        );
+
+        let data = read(&document, u16_bytes(&[0, 1, 0, 2, 0, 3])).unwrap();
+
+        assert_eq!(data, u16_bytes(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn sparse_values_replace_view_data() {
+        //base data is [1, 2, 3, 4], then indices [1, 3] and values [20, 40]
+        let document = get_document(16,
+            r#"{ "buffer": 0, "byteLength": 8 }, { "buffer": 0, "byteOffset": 8, "byteLength": 4 }, { "buffer": 0, "byteOffset": 12, "byteLength": 4 }"#,
+            r#"{ "bufferView": 0, "componentType": 5123, "count": 4, "type": "SCALAR", "sparse": {
+                "count": 2,
+                "indices": { "bufferView": 1, "componentType": 5123 },
+                "values": { "bufferView": 2 }
+            }}"#
+        );
+        let buffer = [u16_bytes(&[1, 2, 3, 4]), u16_bytes(&[1, 3]), u16_bytes(&[20, 40])].concat();
+
+        let data = read(&document, buffer).unwrap();
+
+        assert_eq!(data, u16_bytes(&[1, 20, 3, 40]));
+    }
+
+    #[test]
+    fn sparse_without_view_is_zero_filled() {
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 4 }, { "buffer": 0, "byteOffset": 4, "byteLength": 4 }"#,
+            r#"{ "componentType": 5123, "count": 4, "type": "SCALAR", "sparse": {
+                "count": 2,
+                "indices": { "bufferView": 0, "componentType": 5123 },
+                "values": { "bufferView": 1 }
+            }}"#
+        );
+        let buffer = [u16_bytes(&[0, 2]), u16_bytes(&[7, 8])].concat();
+
+        let data = read(&document, buffer).unwrap();
+
+        assert_eq!(data, u16_bytes(&[7, 0, 8, 0]));
+    }
+
+    #[test]
+    fn sparse_on_strided_view() {
+        let document = get_document(20,
+            r#"{ "buffer": 0, "byteLength": 12, "byteStride": 4 }, { "buffer": 0, "byteOffset": 12, "byteLength": 4 }, { "buffer": 0, "byteOffset": 16, "byteLength": 4 }"#,
+            r#"{ "bufferView": 0, "componentType": 5123, "count": 3, "type": "SCALAR", "sparse": {
+                "count": 1,
+                "indices": { "bufferView": 1, "componentType": 5125 },
+                "values": { "bufferView": 2 }
+            }}"#
+        );
+        let buffer = [u16_bytes(&[1, 0, 2, 0, 3, 0]), u32_bytes(&[2]), u16_bytes(&[30, 0])].concat();
+
+        let data = read(&document, buffer).unwrap();
+
+        assert_eq!(data, u16_bytes(&[1, 2, 30]));
+    }
+
+    #[test]
+    fn sparse_index_types() {
+        //5121 = u8, 5123 = u16, 5125 = u32
+        let indices:[(u32, Vec<u8>); 3] = [
+            (5121, vec![1, 2, 0, 0]),
+            (5123, u16_bytes(&[1, 2])),
+            (5125, u32_bytes(&[1, 2])),
+        ];
+
+        for (component_type, index_bytes) in indices.iter() {
+            let index_len = index_bytes.len();
+            let document = get_document(8 + index_len + 4,
+                &format!(r#"{{ "buffer": 0, "byteLength": 8 }}, {{ "buffer": 0, "byteOffset": 8, "byteLength": {} }}, {{ "buffer": 0, "byteOffset": {}, "byteLength": 4 }}"#, index_len, 8 + index_len),
+                &format!(r#"{{ "bufferView": 0, "componentType": 5123, "count": 4, "type": "SCALAR", "sparse": {{
+                    "count": 2,
+                    "indices": {{ "bufferView": 1, "componentType": {} }},
+                    "values": {{ "bufferView": 2 }}
+                }}}}"#, component_type)
+            );
+            let buffer = [u16_bytes(&[1, 2, 3, 4]), index_bytes.clone(), u16_bytes(&[20, 30])].concat();
+
+            let data = read(&document, buffer).unwrap();
+
+            assert_eq!(data, u16_bytes(&[1, 20, 30, 4]), "index component type {}", component_type);
         }
     }
-}
 
-fn make_accessor_info(webgl:&mut WebGl2Renderer, accessor:&gltf::accessor::Accessor, buffer_view_ids:&mut Vec<Id>) -> Result<AccessorInfo, Error> {
-    let accessor_id = accessor.index();
+    #[test]
+    fn sparse_index_out_of_range() {
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 4 }, { "buffer": 0, "byteOffset": 4, "byteLength": 4 }"#,
+            r#"{ "componentType": 5123, "count": 2, "type": "SCALAR", "sparse": {
+                "count": 1,
+                "indices": { "bufferView": 0, "componentType": 5125 },
+                "values": { "bufferView": 1 }
+            }}"#
+        );
+        let buffer = [u32_bytes(&[2]), u16_bytes(&[5, 0])].concat();
 
-    let byte_len = get_byte_length(&accessor);
-    //TODO - remove the temp Some wrapper
-    //https://github.com/gltf-rs/gltf/issues/266
-    match Some(accessor.view()) {
-        None => {
-            if accessor.sparse().is_none() {
-                return Err(NativeError::AccessorSparse.into())
-            }
+        let err = read(&document, buffer).unwrap_err();
 
-            let buffer_id = webgl.create_buffer()?;
-            let data = get_accessor_data(accessor)?;
-            //TODO - create and fill buffer with 0's
+        assert!(matches!(err, Error::Native(NativeError::AccessorSparseIndex(0, 2))));
+    }
 
+    #[test]
+    fn view_out_of_bounds() {
+        //the document claims more data than the buffer has
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 8 }"#,
+            r#"{ "bufferView": 0, "componentType": 5123, "count": 4, "type": "SCALAR" }"#
+        );
 
-            Ok(AccessorInfo{
-                base: BaseAccessorInfo{
-                    len: byte_len,
-                    offset: 0,
-                    component_type: accessor.data_type(),
-                    dim_type: accessor.dimensions(),
-                    buffer_id
-                },
-                sparse: None
-             })
-        },
-        Some(view) => {
-            let offset = get_byte_offset(&view, &accessor);
-            let stride_len = get_byte_stride_len(&view, &accessor);
-
-            Ok(AccessorInfo{
-                base: BaseAccessorInfo {
-                    len: byte_len + stride_len,
-                    offset,
-                    component_type: accessor.data_type(),
-                    dim_type: accessor.dimensions(),
-                    buffer_id: buffer_view_ids[view.index()]
-                },
-                sparse: None
-            })
+        let err = read(&document, u16_bytes(&[1, 2])).unwrap_err();
+
+        assert!(matches!(err, Error::Native(NativeError::AccessorBounds(0, 8, 4))));
+    }
+
+    #[test]
+    fn sparse_values_out_of_bounds() {
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 4 }, { "buffer": 0, "byteOffset": 4, "byteLength": 4 }"#,
+            r#"{ "componentType": 5123, "count": 4, "type": "SCALAR", "sparse": {
+                "count": 2,
+                "indices": { "bufferView": 0, "componentType": 5123 },
+                "values": { "bufferView": 1 }
+            }}"#
+        );
+
+        let err = read(&document, [u16_bytes(&[0, 1]), u16_bytes(&[7])].concat()).unwrap_err();
+
+        assert!(matches!(err, Error::Native(NativeError::AccessorBounds(4, 8, 6))));
+    }
+
+    fn get_options(document:&gltf::Document, index:usize) -> Result<AttributeOptions, Error> {
+        let accessor = document.accessors().nth(index).unwrap();
+        get_attribute_options(&accessor, &AccessorInfo::new(&accessor))
+    }
+
+    #[test]
+    fn interleaved_attribute_options() {
+        //position (vec3 f32) then normal (vec3 f32), interleaved
+        let document = get_document(72,
+            r#"{ "buffer": 0, "byteLength": 72, "byteStride": 24 }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+               { "bufferView": 0, "byteOffset": 12, "componentType": 5126, "count": 3, "type": "VEC3" }"#
+        );
+
+        let position = get_options(&document, 0).unwrap();
+        let normal = get_options(&document, 1).unwrap();
+
+        assert_eq!((position.size, position.stride, position.offset), (3, 24, 0));
+        assert_eq!((normal.size, normal.stride, normal.offset), (3, 24, 12));
+        assert!(matches!(normal.data_type, awsm_web::webgl::DataType::Float));
+        assert!(!normal.normalized);
+    }
+
+    #[test]
+    fn sparse_attribute_options_are_packed() {
+        //the base view is strided and offset, but sparse data is uploaded on its own
+        let document = get_document(40,
+            r#"{ "buffer": 0, "byteLength": 32, "byteStride": 8 }, { "buffer": 0, "byteOffset": 32, "byteLength": 2 }, { "buffer": 0, "byteOffset": 36, "byteLength": 4 }"#,
+            r#"{ "bufferView": 0, "byteOffset": 4, "componentType": 5126, "count": 4, "type": "SCALAR", "sparse": {
+                "count": 1,
+                "indices": { "bufferView": 1, "componentType": 5123 },
+                "values": { "bufferView": 2 }
+            }}"#
+        );
+
+        let options = get_options(&document, 0).unwrap();
+
+        assert_eq!((options.stride, options.offset), (0, 0));
+    }
+
+    #[test]
+    fn normalized_u16_texcoords() {
+        let document = get_document(8,
+            r#"{ "buffer": 0, "byteLength": 8 }"#,
+            r#"{ "bufferView": 0, "componentType": 5123, "normalized": true, "count": 2, "type": "VEC2" }"#
+        );
+
+        let options = get_options(&document, 0).unwrap();
+
+        assert_eq!(options.size, 2);
+        assert!(matches!(options.data_type, awsm_web::webgl::DataType::UnsignedShort));
+        assert!(options.normalized);
+        assert_eq!((options.stride, options.offset), (0, 0));
+    }
+
+    #[test]
+    fn matrix_attributes_are_rejected() {
+        let document = get_document(64,
+            r#"{ "buffer": 0, "byteLength": 64 }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 1, "type": "MAT2" },
+               { "bufferView": 0, "componentType": 5126, "count": 1, "type": "MAT3" },
+               { "bufferView": 0, "componentType": 5126, "count": 1, "type": "MAT4" },
+               { "bufferView": 0, "componentType": 5126, "count": 1, "type": "VEC4" }"#
+        );
+
+        for index in 0..3 {
+            let err = get_options(&document, index).err().unwrap();
+            assert!(matches!(err, Error::Native(NativeError::AccessorDimensions("vertex attribute"))));
         }
+        assert_eq!(get_options(&document, 3).unwrap().size, 4);
     }
 }
-
-*/
\ No newline at end of file
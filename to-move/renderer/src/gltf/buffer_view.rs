@@ -1,6 +1,6 @@
 use gltf::buffer::View;
 
-pub fn get_buffer_view_data <'a>(view:&View, buffers:&'a Vec<Vec<u8>>) -> &'a [u8] {
+pub fn get_buffer_view_data <'a>(view:&View, buffers:&'a [Vec<u8>]) -> &'a [u8] {
     let byte_offset = view.offset();
     let byte_length = view.length();
     let byte_end = byte_offset + byte_length;
@@ -0,0 +1,319 @@
+use crate::errors::Error;
+use super::loader::{GltfResource, GltfFileType, load_gltf};
+use web_sys::HtmlImageElement;
+use futures::Future;
+use futures::future::{self, Either, FutureExt, TryFutureExt, Shared, LocalBoxFuture};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+type SharedLoad<I> = Shared<LocalBoxFuture<'static, Result<Rc<GltfResource<I>>, Error>>>;
+
+enum CacheEntry<I> {
+    Loading(SharedLoad<I>),
+    Loaded(Rc<GltfResource<I>>),
+}
+
+/// De-dups loads by url
+/// Loading the same url while it's in-flight waits on the same fetch
+/// and once it's loaded, the same resource is returned
+///
+/// Pass the resource to Renderer::upload_shared_gltf() to also share the GPU data
+pub struct GltfCache<I = HtmlImageElement> {
+    entries: Rc<RefCell<HashMap<String, CacheEntry<I>>>>
+}
+
+//derived impls would require I: Clone / Default
+impl<I> Clone for GltfCache<I> {
+    fn clone(&self) -> Self {
+        Self { entries: Rc::clone(&self.entries) }
+    }
+}
+
+impl<I> Default for GltfCache<I> {
+    fn default() -> Self {
+        Self { entries: Rc::new(RefCell::new(HashMap::new())) }
+    }
+}
+
+impl GltfCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads via load_gltf() if it isn't cached or in-flight
+    pub fn load(&self, url:&str, file_type: Option<GltfFileType>) -> impl Future<Output = Result<Rc<GltfResource>, Error>> {
+        self.load_with(url, move |url| load_gltf(url, file_type))
+    }
+}
+
+impl<I: 'static> GltfCache<I> {
+    /// Same as load() but the loader is only called if the url isn't cached or in-flight
+    /// e.g. to load through a different GltfFetcher
+    /// It gets the url as it was passed in (not the normalized key)
+    /// and the future it returns has to own everything it uses
+    pub fn load_with<L, F>(&self, url:&str, loader:L) -> impl Future<Output = Result<Rc<GltfResource<I>>, Error>>
+    where 
+        L: FnOnce(&str) -> F,
+        F: Future<Output = Result<GltfResource<I>, Error>> + 'static
+    {
+        let key = normalize_url(url);
+
+        let shared = {
+            let mut entries = self.entries.borrow_mut();
+
+            match entries.get(&key) {
+                Some(CacheEntry::Loaded(resource)) => {
+                    Either::Left(future::ready(Ok(Rc::clone(resource))))
+                },
+                Some(CacheEntry::Loading(shared)) => {
+                    Either::Right(shared.clone())
+                },
+                None => {
+                    let shared = loader(url)
+                        .map_ok(Rc::new)
+                        .boxed_local()
+                        .shared();
+
+                    entries.insert(key.clone(), CacheEntry::Loading(shared.clone()));
+                    Either::Right(shared)
+                }
+            }
+        };
+
+        let entries = Rc::clone(&self.entries);
+
+        async move {
+            let result = shared.await;
+
+            let mut entries = entries.borrow_mut();
+            //only touch it if it wasn't removed or replaced in the meantime
+            if let Some(CacheEntry::Loading(_)) = entries.get(&key) {
+                match &result {
+                    Ok(resource) => {
+                        entries.insert(key, CacheEntry::Loaded(Rc::clone(resource)));
+                    },
+                    //failed loads aren't cached so they can be retried
+                    Err(_) => {
+                        entries.remove(&key);
+                    }
+                }
+            }
+
+            result
+        }
+    }
+
+    /// Only returns completed loads
+    pub fn get(&self, url:&str) -> Option<Rc<GltfResource<I>>> {
+        match self.entries.borrow().get(&normalize_url(url)) {
+            Some(CacheEntry::Loaded(resource)) => Some(Rc::clone(resource)),
+            _ => None
+        }
+    }
+
+    pub fn contains(&self, url:&str) -> bool {
+        self.entries.borrow().contains_key(&normalize_url(url))
+    }
+
+    /// The resource stays alive for anyone still holding it
+    pub fn remove(&self, url:&str) {
+        self.entries.borrow_mut().remove(&normalize_url(url));
+    }
+
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
+
+/// Makes urls that point to the same file compare equal
+/// 1. backslashes become forward slashes
+/// 2. fragments are dropped (querystrings are kept since they can change the response)
+/// 3. "." and ".." path segments are resolved
+pub fn normalize_url(url:&str) -> String {
+    let url = url.replace('\\', "/");
+    let url = url.split('#').next().unwrap_or("");
+
+    let (path, query) = match url.find('?') {
+        Some(idx) => (&url[..idx], &url[idx..]),
+        None => (url, "")
+    };
+
+    //keep the scheme and host as-is
+    let (prefix, path) = match path.find("://") {
+        Some(idx) => {
+            let after_scheme = idx + 3;
+            match path[after_scheme..].find('/') {
+                Some(host_end) => path.split_at(after_scheme + host_end),
+                None => (path, "")
+            }
+        },
+        None => ("", path)
+    };
+
+    let mut segments:Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "." => {},
+            ".." => {
+                match segments.last() {
+                    Some(last) if *last != ".." && !last.is_empty() => { segments.pop(); },
+                    _ => segments.push(segment)
+                }
+            },
+            _ => segments.push(segment)
+        }
+    }
+
+    format!("{}{}{}", prefix, segments.join("/"), query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::NativeError;
+    use futures::channel::oneshot;
+    use futures::executor::block_on;
+    use std::cell::Cell;
+
+    //the images aren't looked at, so they're just numbers
+    type TestCache = GltfCache<u32>;
+
+    fn get_resource() -> GltfResource<u32> {
+        let json = r#"{ "asset": { "version": "2.0" } }"#;
+        GltfResource {
+            gltf: gltf::Gltf::from_slice(json.as_bytes()).unwrap().document,
+            buffers: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    fn load(cache:&TestCache, url:&str, calls:&Rc<Cell<usize>>) -> Result<Rc<GltfResource<u32>>, Error> {
+        let calls = Rc::clone(calls);
+        block_on(cache.load_with(url, move |_| {
+            calls.set(calls.get() + 1);
+            future::ready(Ok(get_resource()))
+        }))
+    }
+
+    fn fail(cache:&TestCache, url:&str) -> Result<Rc<GltfResource<u32>>, Error> {
+        block_on(cache.load_with(url, |url| future::ready(Err(NativeError::FetchMissing(url.to_string()).into()))))
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let cache = TestCache::default();
+        let calls = Rc::new(Cell::new(0));
+
+        assert!(!cache.contains("a.gltf"));
+        assert!(cache.get("a.gltf").is_none());
+
+        let first = load(&cache, "a.gltf", &calls).unwrap();
+        let second = load(&cache, "./a.gltf", &calls).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(Rc::ptr_eq(&first, &cache.get("a.gltf").unwrap()));
+
+        load(&cache, "b.gltf", &calls).unwrap();
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn remove_and_clear() {
+        let cache = TestCache::default();
+        let calls = Rc::new(Cell::new(0));
+
+        let first = load(&cache, "a.gltf", &calls).unwrap();
+        load(&cache, "b.gltf", &calls).unwrap();
+
+        cache.remove("a.gltf");
+        assert!(!cache.contains("a.gltf"));
+        assert!(cache.contains("b.gltf"));
+
+        //still alive for whoever holds it, but loaded again from the cache
+        let reloaded = load(&cache, "a.gltf", &calls).unwrap();
+        assert_eq!(calls.get(), 3);
+        assert!(!Rc::ptr_eq(&first, &reloaded));
+
+        cache.clear();
+        assert!(!cache.contains("a.gltf"));
+        assert!(!cache.contains("b.gltf"));
+    }
+
+    #[test]
+    fn concurrent_loads_share_one_future() {
+        let cache = TestCache::default();
+        let calls = Rc::new(Cell::new(0));
+        let (sender, receiver) = oneshot::channel();
+
+        let first = {
+            let calls = Rc::clone(&calls);
+            cache.load_with("models/a.gltf", move |_| {
+                calls.set(calls.get() + 1);
+                async move { receiver.await.unwrap() }
+            })
+        };
+        let second = {
+            let calls = Rc::clone(&calls);
+            cache.load_with("models/../models/a.gltf#node", move |_| {
+                calls.set(calls.get() + 1);
+                future::ready(Ok(get_resource()))
+            })
+        };
+
+        //in-flight
+        assert_eq!(calls.get(), 1);
+        assert!(cache.contains("models/a.gltf"));
+        assert!(cache.get("models/a.gltf").is_none());
+
+        sender.send(Ok(get_resource())).ok().unwrap();
+        let (first, second) = block_on(future::join(first, second));
+
+        assert_eq!(calls.get(), 1);
+        assert!(Rc::ptr_eq(&first.unwrap(), &second.unwrap()));
+        assert!(cache.get("models/a.gltf").is_some());
+    }
+
+    #[test]
+    fn failure_is_evicted() {
+        let cache = TestCache::default();
+        let calls = Rc::new(Cell::new(0));
+
+        assert!(matches!(fail(&cache, "a.gltf"), Err(Error::Native(NativeError::FetchMissing(_)))));
+        assert!(!cache.contains("a.gltf"));
+
+        //so it can be retried
+        load(&cache, "a.gltf", &calls).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert!(cache.get("a.gltf").is_some());
+    }
+
+    #[test]
+    fn removed_while_loading() {
+        let cache = TestCache::default();
+        let (sender, receiver) = oneshot::channel();
+
+        let loading = cache.load_with("a.gltf", |_| async move { receiver.await.unwrap() });
+        cache.remove("a.gltf");
+
+        sender.send(Ok(get_resource())).ok().unwrap();
+        block_on(loading).unwrap();
+
+        //it finished, but isn't put back
+        assert!(!cache.contains("a.gltf"));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_url("models\\duck\\duck.gltf"), "models/duck/duck.gltf");
+        assert_eq!(normalize_url("models/./duck/../duck.gltf"), "models/duck.gltf");
+        assert_eq!(normalize_url("../models/duck.gltf"), "../models/duck.gltf");
+        assert_eq!(normalize_url("a/../../duck.gltf"), "../duck.gltf");
+        assert_eq!(normalize_url("duck.gltf#mesh"), "duck.gltf");
+        assert_eq!(normalize_url("duck.gltf?v=2#mesh"), "duck.gltf?v=2");
+        assert_eq!(normalize_url("duck.gltf?path=../x"), "duck.gltf?path=../x");
+        assert_eq!(normalize_url("https://example.com/a/../duck.gltf"), "https://example.com/duck.gltf");
+        assert_eq!(normalize_url("https://example.com"), "https://example.com");
+        assert_eq!(normalize_url("/assets/./duck.gltf"), "/assets/duck.gltf");
+    }
+}
@@ -0,0 +1,170 @@
+use crate::errors::{Error, NativeError};
+
+/// A parsed data: uri
+/// See https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/Data_URIs
+pub struct DataUri<'a> {
+    pub mime_type: &'a str,
+    pub base64: bool,
+    pub data: &'a str,
+}
+
+impl <'a> DataUri<'a> {
+    /// Returns None if it's not a data: uri at all
+    pub fn parse(uri:&'a str) -> Option<Result<Self, Error>> {
+        if !uri.starts_with("data:") {
+            return None;
+        }
+
+        let uri = &uri[5..];
+
+        Some(match uri.find(',') {
+            None => Err(NativeError::DataUri("missing comma".to_string()).into()),
+            Some(idx) => {
+                let header = &uri[..idx];
+                let data = &uri[idx+1..];
+                let mut params = header.split(';');
+                let mime_type = params.next().unwrap_or("");
+                let base64 = params.any(|param| param == "base64");
+
+                Ok(Self { mime_type, base64, data })
+            }
+        })
+    }
+
+    pub fn decode(&self) -> Result<Vec<u8>, Error> {
+        if self.base64 {
+            decode_base64(self.data)
+        } else {
+            decode_percent(self.data)
+        }
+    }
+
+    /// Decodes the data if it's a valid buffer (i.e. one of the two mime types allowed by glTF)
+    pub fn decode_buffer(&self) -> Result<Vec<u8>, Error> {
+        match self.mime_type {
+            "application/octet-stream" | "application/gltf-buffer" => self.decode(),
+            _ => Err(NativeError::DataUriMimeType(self.mime_type.to_string()).into())
+        }
+    }
+}
+
+/// Non-base64 data is percent-encoded (e.g. %00 for a zero byte)
+pub fn decode_percent(input:&str) -> Result<Vec<u8>, Error> {
+    let input = input.as_bytes();
+    let mut output = Vec::with_capacity(input.len());
+    let mut index = 0;
+
+    while index < input.len() {
+        if input[index] == b'%' {
+            let byte = input
+                .get(index+1..index+3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Error::from(NativeError::DataUri(format!("invalid percent encoding at {}", index))))?;
+            output.push(byte);
+            index += 3;
+        } else {
+            output.push(input[index]);
+            index += 1;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Standard base64 (with optional padding)
+/// Written inline to avoid pulling in another crate for this one thing
+pub fn decode_base64(input:&str) -> Result<Vec<u8>, Error> {
+    fn decode_char(c:u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None
+        }
+    }
+
+    let input = input.trim_end_matches('=').as_bytes();
+
+    if input.len() % 4 == 1 {
+        return Err(NativeError::DataUri("invalid base64 length".to_string()).into());
+    }
+
+    let mut output = Vec::with_capacity((input.len() * 3) / 4);
+
+    for chunk in input.chunks(4) {
+        let mut bits:u32 = 0;
+        for (index, c) in chunk.iter().enumerate() {
+            let value = decode_char(*c)
+                .ok_or_else(|| Error::from(NativeError::DataUri(format!("invalid base64 character '{}'", *c as char))))?;
+            bits |= value << (18 - (index * 6));
+        }
+
+        let bytes = bits.to_be_bytes();
+        //a chunk of n chars holds n-1 bytes (4 chars -> 3 bytes, 3 -> 2, 2 -> 1)
+        output.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_buffer(uri:&str) -> Result<Vec<u8>, Error> {
+        DataUri::parse(uri).unwrap().and_then(|data_uri| data_uri.decode_buffer())
+    }
+
+    #[test]
+    fn not_a_data_uri() {
+        assert!(DataUri::parse("scene.bin").is_none());
+        assert!(DataUri::parse("http://example.com/data:").is_none());
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_buffer("data:application/octet-stream;base64,AAECAwQ=").unwrap(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(decode_buffer("data:application/gltf-buffer;base64,/w==").unwrap(), vec![255]);
+        //unpadded and url-safe
+        assert_eq!(decode_buffer("data:application/octet-stream;base64,-_8").unwrap(), vec![251, 255]);
+        assert_eq!(decode_buffer("data:application/octet-stream;base64,").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn percent_encoded() {
+        assert_eq!(decode_buffer("data:application/octet-stream,%00%01%ff").unwrap(), vec![0, 1, 255]);
+        assert_eq!(decode_buffer("data:application/octet-stream,ab%20c").unwrap(), b"ab c".to_vec());
+    }
+
+    #[test]
+    fn malformed() {
+        let is_malformed = |uri| matches!(decode_buffer(uri), Err(Error::Native(NativeError::DataUri(_))));
+
+        assert!(is_malformed("data:application/octet-stream;base64"));
+        assert!(is_malformed("data:application/octet-stream;base64,AAEC*wQ="));
+        assert!(is_malformed("data:application/octet-stream;base64,AAECA"));
+        assert!(is_malformed("data:application/octet-stream,%0"));
+        assert!(is_malformed("data:application/octet-stream,%zz"));
+    }
+
+    #[test]
+    fn wrong_mime_type() {
+        match decode_buffer("data:image/png;base64,AAEC") {
+            Err(Error::Native(NativeError::DataUriMimeType(mime_type))) => assert_eq!(mime_type, "image/png"),
+            _ => panic!("expected a mime type error")
+        }
+
+        assert!(matches!(decode_buffer("data:;base64,AAEC"), Err(Error::Native(NativeError::DataUriMimeType(_)))));
+    }
+
+    #[test]
+    fn images_decode_any_mime_type() {
+        let data_uri = DataUri::parse("data:image/png;base64,AAEC").unwrap().unwrap();
+
+        assert_eq!(data_uri.mime_type, "image/png");
+        assert_eq!(data_uri.decode().unwrap(), vec![0, 1, 2]);
+    }
+}
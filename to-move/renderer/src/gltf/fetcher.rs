@@ -0,0 +1,166 @@
+use web_sys::{HtmlImageElement};
+use awsm_web::loaders::{fetch};
+use crate::errors::{Error, NativeError};
+use futures::future::{self, LocalBoxFuture, FutureExt, TryFutureExt};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Where the loader gets its bytes and images from
+/// The urls are already resolved against the base path of the gltf file
+///
+/// The Image type is whatever the fetcher produces for glTF images
+/// On the web that's an HtmlImageElement, elsewhere it's the encoded bytes
+pub trait GltfFetcher {
+    type Image;
+
+    fn fetch_bytes<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>>;
+
+    fn fetch_image<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>>;
+
+    /// for images that are embedded in a buffer view
+    fn decode_image<'a>(&'a self, bytes:&'a [u8], mime_type:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>>;
+}
+
+/// Encoded image data, for fetchers that don't decode
+pub struct EncodedImage {
+    pub bytes: Vec<u8>,
+    pub mime_type: Option<String>,
+}
+
+/// Fetches via the browser (this is what load_gltf uses)
+pub struct WebFetcher;
+
+impl GltfFetcher for WebFetcher {
+    type Image = HtmlImageElement;
+
+    fn fetch_bytes<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        fetch::vec_u8(url).map_err(Error::from).boxed_local()
+    }
+
+    fn fetch_image<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        fetch::image(url).map_err(Error::from).boxed_local()
+    }
+
+    fn decode_image<'a>(&'a self, bytes:&'a [u8], mime_type:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        fetch::image_u8(bytes, mime_type).map_err(Error::from).boxed_local()
+    }
+}
+
+/// Reads from the local filesystem
+/// Urls are treated as paths relative to the root
+pub struct FileFetcher {
+    pub root: PathBuf
+}
+
+impl FileFetcher {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn read(&self, url:&str) -> Result<Vec<u8>, Error> {
+        std::fs::read(self.root.join(url))
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::NotFound => NativeError::FetchMissing(url.to_string()).into(),
+                _ => Error::from(format!("unable to read {}: {}", url, err))
+            })
+    }
+}
+
+impl GltfFetcher for FileFetcher {
+    type Image = EncodedImage;
+
+    fn fetch_bytes<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        future::ready(self.read(url)).boxed_local()
+    }
+
+    fn fetch_image<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        let image = self.read(url).map(|bytes| EncodedImage { bytes, mime_type: None });
+        future::ready(image).boxed_local()
+    }
+
+    fn decode_image<'a>(&'a self, bytes:&'a [u8], mime_type:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        future::ready(Ok(EncodedImage { bytes: bytes.to_vec(), mime_type: Some(mime_type.to_string()) })).boxed_local()
+    }
+}
+
+/// Serves from a map of url -> bytes
+/// Useful for tests, zip archives, drag and drop, etc.
+#[derive(Default)]
+pub struct MemoryFetcher {
+    pub files: HashMap<String, Vec<u8>>
+}
+
+impl MemoryFetcher {
+    pub fn new(files: HashMap<String, Vec<u8>>) -> Self {
+        Self { files }
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, url: S, bytes: Vec<u8>) {
+        self.files.insert(url.into(), bytes);
+    }
+
+    fn read(&self, url:&str) -> Result<Vec<u8>, Error> {
+        self.files
+            .get(url)
+            .cloned()
+            .ok_or_else(|| NativeError::FetchMissing(url.to_string()).into())
+    }
+}
+
+impl GltfFetcher for MemoryFetcher {
+    type Image = EncodedImage;
+
+    fn fetch_bytes<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        future::ready(self.read(url)).boxed_local()
+    }
+
+    fn fetch_image<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        let image = self.read(url).map(|bytes| EncodedImage { bytes, mime_type: None });
+        future::ready(image).boxed_local()
+    }
+
+    fn decode_image<'a>(&'a self, bytes:&'a [u8], mime_type:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        future::ready(Ok(EncodedImage { bytes: bytes.to_vec(), mime_type: Some(mime_type.to_string()) })).boxed_local()
+    }
+}
+
+/// Serves bytes from a map of url -> bytes, but decodes images via the browser
+/// This is what load_gltf_from_files uses
+pub struct WebMemoryFetcher {
+    pub files: MemoryFetcher
+}
+
+impl WebMemoryFetcher {
+    pub fn new(files: HashMap<String, Vec<u8>>) -> Self {
+        Self { files: MemoryFetcher::new(files) }
+    }
+}
+
+impl GltfFetcher for WebMemoryFetcher {
+    type Image = HtmlImageElement;
+
+    fn fetch_bytes<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        self.files.fetch_bytes(url)
+    }
+
+    fn fetch_image<'a>(&'a self, url:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        match self.files.files.get(url) {
+            Some(bytes) => self.decode_image(bytes, get_mime_type(url)),
+            None => future::ready(Err(NativeError::FetchMissing(url.to_string()).into())).boxed_local()
+        }
+    }
+
+    fn decode_image<'a>(&'a self, bytes:&'a [u8], mime_type:&'a str) -> LocalBoxFuture<'a, Result<Self::Image, Error>> {
+        fetch::image_u8(bytes, mime_type).map_err(Error::from).boxed_local()
+    }
+}
+
+//glTF only allows png and jpeg
+fn get_mime_type(url:&str) -> &'static str {
+    let url = url.to_lowercase();
+    if url.ends_with(".jpg") || url.ends_with(".jpeg") {
+        "image/jpeg"
+    } else {
+        "image/png"
+    }
+}
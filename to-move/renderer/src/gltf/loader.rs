@@ -1,30 +1,32 @@
-use web_sys::{HtmlImageElement};
-use awsm_web::loaders::{fetch};
+use web_sys::{HtmlImageElement, Blob};
+use js_sys::Uint8Array;
+use wasm_bindgen_futures::JsFuture;
 use crate::errors::{Error, NativeError};
+use super::data_uri::DataUri;
+use super::progress::{ProgressTracker, ProgressCallback, LoadCancel, cancellable};
+use super::fetcher::{GltfFetcher, WebFetcher, WebMemoryFetcher};
 use gltf::{Gltf, Document, buffer, image, Error as GltfError};
 use futures::{Future};
-use futures::future::{try_join_all, TryFutureExt};
+use futures::future::try_join_all;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
-/// Web-specific adaptation of https://github.com/gltf-rs/gltf/blob/master/src/import.rs
-/// Main differences:
-/// 1. Everything is async
-/// 2. No image_data_reference feature (hence no base64/image crate dependencies)
-/// 3. Some error checking is removed since the web api does it inherently (e.g. mime type)
-/// 4. Adds awsm as a dependency
-///
-/// instead of having awsm as a dependency, the loaders could accept 
-/// generic functions like Fn(&str) -> impl Future<Output=DataResult>
+//Web-specific adaptation of https://github.com/gltf-rs/gltf/blob/master/src/import.rs
+//Main differences:
+//1. Everything is async
+//2. No image_data_reference feature (hence no base64/image crate dependencies)
+//   data: uris are decoded by our own minimal base64 decoder
+//3. Some error checking is removed since the web api does it inherently (e.g. mime type)
+//4. All fetching goes through a GltfFetcher (awsm's fetch on the web)
 
-type DataResult = Result<Vec<u8>, GltfError>;
-type ImageResult = Result<HtmlImageElement, GltfError>;
+type DataResult = Result<Vec<u8>, Error>;
 
 
-pub struct GltfResource {
+pub struct GltfResource<I = HtmlImageElement> {
     pub gltf: Document,
     pub buffers: Vec<Vec<u8>>,
-    pub images: Vec<HtmlImageElement>
+    pub images: Vec<I>
 }
 
 pub enum GltfFileType {
@@ -33,67 +35,141 @@ pub enum GltfFileType {
     Draco //TODO
 }
 
-pub fn get_type_from_filename(_url:&str) -> Option<GltfFileType> {
-    //todo - look for .gltf, .glb, etc.
-    Some(GltfFileType::Json)
+/// Detects the file type from the extension (querystrings and fragments are ignored)
+/// Returns None if the extension isn't known, in which case the loader sniffs the bytes
+pub fn get_type_from_filename(url:&str) -> Option<GltfFileType> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let filename = path.rsplit(['/', '\\']).next().unwrap_or(path);
+
+    filename.rfind('.')
+        .map(|idx| filename[idx+1..].to_lowercase())
+        .and_then(|ext| match ext.as_str() {
+            "gltf" => Some(GltfFileType::Json),
+            "glb" => Some(GltfFileType::Glb),
+            _ => None
+        })
 }
 
+/// If the file type is None, it will be detected by the filename
+/// and failing that, by the magic header after fetching
 pub fn load_gltf(url:&str, file_type: Option<GltfFileType>) -> impl Future<Output = Result<GltfResource, Error>> {
+    let url = url.to_owned();
 
-    let future = {
-        let url = url.to_owned();
-        let file_type = match file_type {
-            Some(file_type) => file_type,
-            None => get_type_from_filename(&url).unwrap_or(GltfFileType::Json)
-        };
+    async move {
+        load_gltf_with(&WebFetcher, &url, file_type).await
+    }
+}
 
-        async move {
-            let Gltf { document, blob } = match file_type {
-                GltfFileType::Json => { 
-                    let text = fetch::text(&url).await?;
-                    let bytes:&[u8] = text.as_bytes();
-                    Gltf::from_slice(bytes)
-                },
-                GltfFileType::Glb => {
-                    let bytes:Vec<u8> = fetch::vec_u8(&url).await?;
-                    Gltf::from_slice(&bytes)
-                },
-                _ => return Err(Error::from(NativeError::GltfLoader))
-            }?;
+/// Same as load_gltf but reports progress and can be cancelled
+/// Cancelling drops all outstanding fetches and resolves with NativeError::Cancelled
+/// (the browser requests themselves aren't aborted, see LoadCancel)
+pub fn load_gltf_cancellable(url:&str, file_type: Option<GltfFileType>, on_progress: Option<ProgressCallback>) -> (impl Future<Output = Result<GltfResource, Error>>, LoadCancel) {
+    let url = url.to_owned();
 
+    cancellable(async move {
+        load_gltf_with_progress(&WebFetcher, &url, file_type, on_progress).await
+    })
+}
 
-            let base_path = get_base_path(&url);
-            let buffers = import_buffer_data( &document, base_path, blob)
-                .await.map_err(|err| Error::from(err))?;
+/// Same as load_gltf but everything is fetched through the provided fetcher
+pub async fn load_gltf_with<F: GltfFetcher>(fetcher:&F, url:&str, file_type: Option<GltfFileType>) -> Result<GltfResource<F::Image>, Error> {
+    load_gltf_with_progress(fetcher, url, file_type, None).await
+}
 
-            //info!("loaded {} buffers", buffer_data.len());
+/// Same as load_gltf_with but calls on_progress as the file itself, and then each buffer and image, is loaded
+pub async fn load_gltf_with_progress<F: GltfFetcher>(fetcher:&F, url:&str, file_type: Option<GltfFileType>, on_progress: Option<ProgressCallback>) -> Result<GltfResource<F::Image>, Error> {
+    let file_type = file_type.or_else(|| get_type_from_filename(url));
+    let progress = ProgressTracker::new(on_progress);
+
+    progress.begin();
+
+    let gltf = match file_type {
+        Some(GltfFileType::Draco) => return Err(Error::from(NativeError::GltfLoader)),
+        //If it's json or glb then from_slice will do the right thing
+        //if it's unknown, from_slice sniffs the "glTF" magic header
+        _ => {
+            let bytes:Vec<u8> = fetcher.fetch_bytes(url).await?;
+            progress.file_loaded(bytes.len());
+            Gltf::from_slice(&bytes)
+        }
+    }?;
 
-            let images = import_image_data( &document, base_path, &buffers)
-                .await.map_err(|err| Error::from(err))?;
+    import_gltf_tracked(fetcher, gltf, get_base_path(url), &progress).await
+}
 
-            //info!("loaded {} images", image_data.len());
+/// Loads from gltf or glb bytes that are already in memory (e.g. a dropped .glb file)
+/// Any external uris are fetched relative to the page
+pub async fn load_gltf_from_bytes(bytes:&[u8]) -> Result<GltfResource, Error> {
+    import_gltf(&WebFetcher, Gltf::from_slice(bytes)?, "").await
+}
 
-            Ok(GltfResource{ gltf: document, buffers, images })
-        }
-    };
+/// Loads from gltf or glb bytes plus the files it references (e.g. a dropped folder)
+/// The files are keyed by their uri relative to the gltf file (e.g. "scene.bin", "textures/albedo.png")
+pub async fn load_gltf_from_files(bytes:&[u8], files:HashMap<String, Vec<u8>>) -> Result<GltfResource, Error> {
+    let fetcher = WebMemoryFetcher::new(files);
+    import_gltf(&fetcher, Gltf::from_slice(bytes)?, "").await
+}
+
+/// Loads a .gltf or .glb File (or any Blob), e.g. from a drop or an <input type="file">
+/// Any external uris are fetched relative to the page
+pub async fn load_gltf_from_blob(blob:&Blob) -> Result<GltfResource, Error> {
+    let bytes = read_blob(blob).await?;
+    load_gltf_from_bytes(&bytes).await
+}
+
+/// Same as load_gltf_from_files, but with the Files (or Blobs) as they were dropped
+/// The files are keyed by their uri relative to the gltf file (e.g. a File's webkitRelativePath without the folder)
+pub async fn load_gltf_from_blobs(gltf:&Blob, files:&HashMap<String, Blob>) -> Result<GltfResource, Error> {
+    let bytes = read_blob(gltf).await?;
+
+    let datas = try_join_all(files.values().map(read_blob)).await?;
+    let files = files.keys().cloned().zip(datas).collect();
+
+    load_gltf_from_files(&bytes, files).await
+}
+
+/// The whole contents of a Blob (a File is a Blob too)
+pub async fn read_blob(blob:&Blob) -> Result<Vec<u8>, Error> {
+    let buffer = JsFuture::from(blob.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+/// Imports the buffers and images of an already-parsed gltf
+/// This is the common path for all the loaders above
+pub async fn import_gltf<F: GltfFetcher>(fetcher:&F, gltf:Gltf, base_path:&str) -> Result<GltfResource<F::Image>, Error> {
+    import_gltf_tracked(fetcher, gltf, base_path, &ProgressTracker::new(None)).await
+}
+
+async fn import_gltf_tracked<F: GltfFetcher>(fetcher:&F, gltf:Gltf, base_path:&str, progress:&ProgressTracker) -> Result<GltfResource<F::Image>, Error> {
+    let Gltf { document, blob } = gltf;
+
+    progress.start(&document);
+
+    let buffers = import_buffer_data(fetcher, &document, base_path, blob, progress).await?;
 
-    future
+    //info!("loaded {} buffers", buffer_data.len());
+
+    let images = import_image_data(fetcher, &document, base_path, &buffers, progress).await?;
+
+    //info!("loaded {} images", image_data.len());
+
+    Ok(GltfResource{ gltf: document, buffers, images })
 }
 
+/// Everything up to and including the last separator
+/// (i.e. empty for a bare filename, so relative uris stay relative)
 fn get_base_path (url:&str) -> &str {
-    let idx1:i32 = url.rfind('/').map(|n| n as i32).unwrap_or(-1) + 1;
-    let idx2:i32 = url.rfind('\\').map(|n| n as i32).unwrap_or(-1) + 1;
+    let url = url.split(['?', '#']).next().unwrap_or(url);
 
-    if idx1 == 0 && idx2 == 0 {
-        url
-    } else {
-        &url[0..(std::cmp::max(idx1, idx2) as usize)]
+    match url.rfind(['/', '\\']) {
+        Some(idx) => &url[..idx+1],
+        None => ""
     }
 }
 
-async fn import_buffer_data<'a>( document: &'a Document, base: &'a str, blob: Option<Vec<u8>>) -> Result<Vec<Vec<u8>>, GltfError> {
+async fn import_buffer_data<'a, F: GltfFetcher>(fetcher:&'a F, document: &'a Document, base: &'a str, blob: Option<Vec<u8>>, progress:&'a ProgressTracker) -> Result<Vec<Vec<u8>>, Error> {
 
-    let futures = get_buffer_futures(document, base, blob);
+    let futures = get_buffer_futures(fetcher, document, base, blob, progress);
 
     let datas:Vec<Vec<u8>> = try_join_all(futures).await?;
 
@@ -105,10 +181,10 @@ async fn import_buffer_data<'a>( document: &'a Document, base: &'a str, blob: Op
                     buffer: buffer.index(),
                     expected: buffer.length(),
                     actual: data.len(),
-                }
+                }.into()
             );
         }
-        while data.len() % 4 != 0 {
+        while !data.len().is_multiple_of(4) {
             data.push(0);
         }
         buffers.push(data);
@@ -116,7 +192,7 @@ async fn import_buffer_data<'a>( document: &'a Document, base: &'a str, blob: Op
     Ok(buffers)
 }
 
-fn get_buffer_futures<'a>(document:&'a Document, base:&str, blob: Option<Vec<u8>>) -> Vec<impl Future<Output=DataResult> + 'a> {
+fn get_buffer_futures<'a, F: GltfFetcher>(fetcher:&'a F, document:&'a Document, base:&str, blob: Option<Vec<u8>>, progress:&'a ProgressTracker) -> Vec<impl Future<Output=DataResult> + 'a> {
     //these need to be owned by each future simultaneously
     let blob = Rc::new(RefCell::new(blob));
     let base = Rc::new(base.to_owned());
@@ -126,72 +202,342 @@ fn get_buffer_futures<'a>(document:&'a Document, base:&str, blob: Option<Vec<u8>
         let base = Rc::clone(&base);
 
         async move {
-            match buffer.source() {
+            //fetched is the number of bytes that came over the wire
+            //embedded data was already counted as part of the file
+            let (data, fetched) = match buffer.source() {
                 buffer::Source::Uri(uri) => {
-                    let url = get_url(base.as_ref(), uri)?;
-                    fetch::vec_u8(&url)
-                        .map_err(|err| GltfError::from(Error::from(err)))
-                        .await
+                    match DataUri::parse(uri) {
+                        //embedded data is decoded right here, no need to fetch
+                        Some(data_uri) => {
+                            (data_uri.and_then(|data_uri| data_uri.decode_buffer())?, 0)
+                        },
+                        None => {
+                            let url = get_url(base.as_ref(), uri)?;
+                            let data = fetcher.fetch_bytes(&url).await?;
+                            let fetched = data.len();
+                            (data, fetched)
+                        }
+                    }
                 },
                 buffer::Source::Bin => {
-                    blob.borrow_mut().take().ok_or(GltfError::MissingBlob)
+                    let data = blob.borrow_mut().take().ok_or_else(|| Error::from(GltfError::MissingBlob))?;
+                    (data, 0)
                 }
-            }
+            };
+
+            progress.buffer_loaded(fetched);
+            Ok(data)
         } 
     }).collect()
 }
 
-async fn import_image_data<'a>(document: &'a Document, base: &'a str, buffer_data:&'a [Vec<u8>]) -> Result<Vec<HtmlImageElement>, GltfError> {
+async fn import_image_data<'a, F: GltfFetcher>(fetcher:&'a F, document: &'a Document, base: &'a str, buffer_data:&'a [Vec<u8>], progress:&'a ProgressTracker) -> Result<Vec<F::Image>, Error> {
 
-    let futures = get_image_futures(document, base, buffer_data);
+    let futures = get_image_futures(fetcher, document, base, buffer_data, progress);
 
     try_join_all(futures).await
 }
 
 
-fn get_image_futures<'a>(document:&'a Document, base:&str, buffer_data:&'a [Vec<u8>]) -> Vec<impl Future<Output=ImageResult> + 'a> {
+fn get_image_futures<'a, F: GltfFetcher>(fetcher:&'a F, document:&'a Document, base:&str, buffer_data:&'a [Vec<u8>], progress:&'a ProgressTracker) -> Vec<impl Future<Output=Result<F::Image, Error>> + 'a> {
     //these need to be owned by each future simultaneously
     let base = Rc::new(base.to_owned());
 
     document.images().map(|image| {
         let base = Rc::clone(&base);
         async move {
-            match image.source() {
-                image::Source::Uri { uri, mime_type: _ } => {
-
-                    let url = get_url(base.as_ref(), uri)?;
-
-                    fetch::image(&url)
-                        .map_err(|err| GltfError::from(Error::from(err)))
-                        .await
+            let image = match image.source() {
+                image::Source::Uri { uri, mime_type } => {
+                    match DataUri::parse(uri) {
+                        Some(data_uri) => {
+                            let data_uri = data_uri?;
+                            let mime_type = mime_type.unwrap_or(data_uri.mime_type);
+                            let encoded_image = data_uri.decode()?;
+                            fetcher.decode_image(&encoded_image, mime_type).await?
+                        },
+                        None => {
+                            let url = get_url(base.as_ref(), uri)?;
+
+                            fetcher.fetch_image(&url).await?
+                        }
+                    }
                 },
                 image::Source::View { view, mime_type } => {
                     let parent_buffer_data = &buffer_data[view.buffer().index()];
                     let begin = view.offset();
                     let end = begin + view.length();
                     let encoded_image = &parent_buffer_data[begin..end];
-                    fetch::image_u8(&encoded_image, &mime_type)
-                        .map_err(|err| GltfError::from(Error::from(err)))
-                        .await
+                    fetcher.decode_image(encoded_image, mime_type).await?
                 },
-            }
+            };
+
+            progress.image_loaded();
+            Ok(image)
         } 
     }).collect()
 }
 
 
-fn get_url(base:&str, uri: &str) -> Result<String, GltfError> {
+fn get_url(base:&str, uri: &str) -> Result<String, Error> {
     if uri.contains(":") {
         //absolute
-        if uri.starts_with("data:") {
-            Ok(uri.to_owned())
-        } else if uri.starts_with("http:") || uri.starts_with("https://") {
+        if uri.starts_with("data:") || uri.starts_with("http:") || uri.starts_with("https://") {
             Ok(uri.to_owned())
         } else {
-            Err(GltfError::UnsupportedScheme)
+            Err(GltfError::UnsupportedScheme.into())
         }
     } else {
         //relative
         Ok(format!("{}{}", base, uri))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fetcher::{MemoryFetcher, FileFetcher};
+    use super::super::progress::LoadProgress;
+    use futures::executor::block_on;
+
+    const SCENE:&str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [
+            { "byteLength": 6, "uri": "scene.bin" },
+            { "byteLength": 3, "uri": "data:application/octet-stream;base64,AQID" }
+        ],
+        "images": [{ "uri": "textures/albedo.png" }]
+    }"#;
+
+    fn get_scene_files(base_path:&str) -> MemoryFetcher {
+        let mut fetcher = MemoryFetcher::default();
+        fetcher.insert(format!("{}scene.gltf", base_path), SCENE.as_bytes().to_vec());
+        fetcher.insert(format!("{}scene.bin", base_path), vec![10, 11, 12, 13, 14, 15]);
+        fetcher.insert(format!("{}textures/albedo.png", base_path), vec![0x89, b'P', b'N', b'G']);
+        fetcher
+    }
+
+    //a glb with a json chunk and a bin chunk
+    fn get_glb(json:&str, bin:&[u8]) -> Vec<u8> {
+        fn pad(mut data:Vec<u8>, byte:u8) -> Vec<u8> {
+            while !data.len().is_multiple_of(4) {
+                data.push(byte);
+            }
+            data
+        }
+
+        let json = pad(json.as_bytes().to_vec(), b' ');
+        let bin = pad(bin.to_vec(), 0);
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+
+        [
+            b"glTF".to_vec(),
+            2u32.to_le_bytes().to_vec(),
+            (length as u32).to_le_bytes().to_vec(),
+            (json.len() as u32).to_le_bytes().to_vec(),
+            b"JSON".to_vec(),
+            json,
+            (bin.len() as u32).to_le_bytes().to_vec(),
+            b"BIN\0".to_vec(),
+            bin,
+        ].concat()
+    }
+
+    #[test]
+    fn base_path() {
+        assert_eq!(get_base_path("scene.gltf"), "");
+        assert_eq!(get_base_path("/scene.gltf"), "/");
+        assert_eq!(get_base_path("models/scene.gltf"), "models/");
+        assert_eq!(get_base_path("models\\scene.gltf"), "models\\");
+        assert_eq!(get_base_path("http://example.com/models/scene.gltf?v=1/2"), "http://example.com/models/");
+    }
+
+    #[test]
+    fn load_from_memory() {
+        let fetcher = get_scene_files("models/");
+
+        let resource = block_on(load_gltf_with(&fetcher, "models/scene.gltf", None)).unwrap();
+
+        //padded to 4 bytes
+        assert_eq!(resource.buffers, vec![vec![10, 11, 12, 13, 14, 15, 0, 0], vec![1, 2, 3, 0]]);
+        assert_eq!(resource.images.len(), 1);
+        assert_eq!(resource.images[0].bytes, vec![0x89, b'P', b'N', b'G']);
+    }
+
+    #[test]
+    fn load_relative_to_root() {
+        let fetcher = get_scene_files("");
+
+        let resource = block_on(load_gltf_with(&fetcher, "scene.gltf", None)).unwrap();
+
+        assert_eq!(resource.buffers[0], vec![10, 11, 12, 13, 14, 15, 0, 0]);
+    }
+
+    #[test]
+    fn load_glb_by_magic_header() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 5 }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 1, "byteLength": 4 }],
+            "images": [{ "bufferView": 0, "mimeType": "image/png" }]
+        }"#;
+        let mut fetcher = MemoryFetcher::default();
+        //no extension to go by
+        fetcher.insert("download?id=1", get_glb(json, &[1, 2, 3, 4, 5]));
+
+        let resource = block_on(load_gltf_with(&fetcher, "download?id=1", None)).unwrap();
+
+        assert_eq!(resource.buffers, vec![vec![1, 2, 3, 4, 5, 0, 0, 0]]);
+        assert_eq!(resource.images[0].bytes, vec![2, 3, 4, 5]);
+        assert_eq!(resource.images[0].mime_type.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let mut fetcher = get_scene_files("models/");
+        fetcher.files.remove("models/scene.bin");
+
+        let err = block_on(load_gltf_with(&fetcher, "models/scene.gltf", None)).err().unwrap();
+
+        match err {
+            Error::Native(NativeError::FetchMissing(url)) => assert_eq!(url, "models/scene.bin"),
+            err => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn load_bad_data_uri() {
+        let mut fetcher = get_scene_files("");
+        fetcher.insert("scene.gltf", SCENE.replace("octet-stream", "json").into_bytes());
+
+        let err = block_on(load_gltf_with(&fetcher, "scene.gltf", None)).err().unwrap();
+
+        assert!(matches!(err, Error::Native(NativeError::DataUriMimeType(_))));
+    }
+
+    #[test]
+    fn load_short_buffer() {
+        let mut fetcher = get_scene_files("");
+        fetcher.insert("scene.bin", vec![1, 2]);
+
+        assert!(block_on(load_gltf_with(&fetcher, "scene.gltf", None)).is_err());
+    }
+
+    #[test]
+    fn load_from_files() {
+        let root = std::env::temp_dir().join(format!("awsm_renderer_loader_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("models/textures")).unwrap();
+        for (url, bytes) in get_scene_files("models/").files.iter() {
+            std::fs::write(root.join(url), bytes).unwrap();
+        }
+        let fetcher = FileFetcher::new(&root);
+
+        let resource = block_on(load_gltf_with(&fetcher, "models/scene.gltf", None));
+        let missing = block_on(load_gltf_with(&fetcher, "models/other.gltf", None));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(resource.unwrap().buffers[0], vec![10, 11, 12, 13, 14, 15, 0, 0]);
+        assert!(matches!(missing, Err(Error::Native(NativeError::FetchMissing(_)))));
+    }
+
+    fn load_with_progress(fetcher:&MemoryFetcher, url:&str) -> Vec<LoadProgress> {
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let on_progress = {
+            let updates = Rc::clone(&updates);
+            Box::new(move |progress:&LoadProgress| updates.borrow_mut().push(progress.clone()))
+        };
+
+        block_on(load_gltf_with_progress(fetcher, url, None, Some(on_progress))).unwrap();
+
+        let updates = updates.borrow().clone();
+        updates
+    }
+
+    #[test]
+    fn progress_reports_the_file() {
+        let updates = load_with_progress(&get_scene_files(""), "scene.gltf");
+
+        //begin, file, start, 2 buffers, 1 image
+        assert_eq!(updates.len(), 6);
+        assert!(!updates[0].file_loaded);
+        assert_eq!(updates[0].bytes_loaded, 0);
+        assert!(updates[1].file_loaded);
+        assert_eq!(updates[1].bytes_loaded, SCENE.len());
+
+        let last = updates.last().unwrap();
+        assert_eq!((last.items_loaded(), last.items_total()), (3, 3));
+        //the data uri is part of the file, only scene.bin is fetched on top of it
+        assert_eq!(last.bytes_loaded, SCENE.len() + 6);
+        //the external image's size isn't known
+        assert_eq!(last.bytes_total, None);
+    }
+
+    #[test]
+    fn progress_counts_glb_bin_chunk_with_the_file() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 4 }]
+        }"#;
+        let glb = get_glb(json, &[1, 2, 3, 4]);
+        let mut fetcher = MemoryFetcher::default();
+        fetcher.insert("scene.glb", glb.clone());
+
+        let updates = load_with_progress(&fetcher, "scene.glb");
+
+        assert_eq!(updates[1].bytes_loaded, glb.len());
+        assert_eq!(updates[2].bytes_total, Some(glb.len()));
+
+        let last = updates.last().unwrap();
+        assert_eq!(last.buffers_loaded, 1);
+        assert_eq!(last.bytes_loaded, glb.len());
+    }
+
+    #[test]
+    fn cancel_before_load() {
+        let fetcher = get_scene_files("");
+        let (future, cancel) = cancellable(load_gltf_with(&fetcher, "scene.gltf", None));
+
+        cancel.cancel();
+
+        assert!(matches!(block_on(future), Err(Error::Native(NativeError::Cancelled))));
+    }
+
+    fn is_json(url:&str) -> bool {
+        matches!(get_type_from_filename(url), Some(GltfFileType::Json))
+    }
+
+    fn is_glb(url:&str) -> bool {
+        matches!(get_type_from_filename(url), Some(GltfFileType::Glb))
+    }
+
+    #[test]
+    fn type_from_extension() {
+        assert!(is_json("scene.gltf"));
+        assert!(is_glb("scene.glb"));
+        assert!(is_glb("http://example.com/models/Scene.GLB"));
+    }
+
+    #[test]
+    fn type_ignores_querystring_and_fragment() {
+        assert!(is_glb("model.glb?v=3"));
+        assert!(is_glb("model.glb#node"));
+        assert!(is_json("model.gltf?v=3#node"));
+        assert!(is_glb("model.glb?file=other.gltf"));
+        assert!(is_json("model.gltf#other.glb"));
+    }
+
+    #[test]
+    fn type_from_last_path_segment() {
+        assert!(is_glb("models\\scene.glb"));
+        assert!(is_json("models.glb/scene.gltf"));
+        assert!(get_type_from_filename("models.glb/scene").is_none());
+        assert!(get_type_from_filename("models.glb\\scene").is_none());
+    }
+
+    #[test]
+    fn type_unknown() {
+        assert!(get_type_from_filename("scene").is_none());
+        assert!(get_type_from_filename("scene?format=glb").is_none());
+        assert!(get_type_from_filename("scene.obj").is_none());
+        assert!(get_type_from_filename("").is_none());
+    }
+}
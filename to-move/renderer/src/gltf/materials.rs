@@ -1,7 +1,119 @@
-/*
-use awsm_web::webgl::{ Id };
+use awsm_web::webgl::Id;
+use crate::errors::Error;
+use crate::materials::{PbrMaterial, MaterialTexture, AlphaMode};
+use super::processor::ProcessState;
+use crate::textures::{SamplerOptions, MinFilter, MagFilter, WrapMode};
+use gltf::texture::{MinFilter as GltfMinFilter, MagFilter as GltfMagFilter, WrappingMode};
 
-pub struct Material {
-    shader_id: Id
+/// Gets the material for the primitive, importing it first if needed
+/// Primitives without a material get the glTF default material
+pub(crate) fn get_material(state:&mut ProcessState, material:&gltf::Material) -> Result<PbrMaterial, Error> {
+    let index = match material.index() {
+        Some(index) => index,
+        None => return Ok(PbrMaterial::default())
+    };
+
+    if state.uploads.materials[index].is_none() {
+        state.uploads.materials[index] = Some(import_material(state, material)?);
+    }
+
+    Ok(state.uploads.materials[index].clone().unwrap())
+}
+
+fn import_material(state:&mut ProcessState, material:&gltf::Material) -> Result<PbrMaterial, Error> {
+    let pbr = material.pbr_metallic_roughness();
+
+    let base_color_texture = match pbr.base_color_texture() {
+        Some(info) => Some(get_material_texture(state, &info.texture(), info.tex_coord())?),
+        None => None
+    };
+    let metallic_roughness_texture = match pbr.metallic_roughness_texture() {
+        Some(info) => Some(get_material_texture(state, &info.texture(), info.tex_coord())?),
+        None => None
+    };
+    let (normal_texture, normal_scale) = match material.normal_texture() {
+        Some(info) => (Some(get_material_texture(state, &info.texture(), info.tex_coord())?), info.scale()),
+        None => (None, 1.0)
+    };
+    let (occlusion_texture, occlusion_strength) = match material.occlusion_texture() {
+        Some(info) => (Some(get_material_texture(state, &info.texture(), info.tex_coord())?), info.strength()),
+        None => (None, 1.0)
+    };
+    let emissive_texture = match material.emissive_texture() {
+        Some(info) => Some(get_material_texture(state, &info.texture(), info.tex_coord())?),
+        None => None
+    };
+
+    Ok(PbrMaterial {
+        base_color_factor: pbr.base_color_factor(),
+        base_color_texture,
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture,
+        normal_texture,
+        normal_scale,
+        occlusion_texture,
+        occlusion_strength,
+        emissive_texture,
+        emissive_factor: material.emissive_factor(),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff(),
+        double_sided: material.double_sided(),
+    })
+}
+
+fn get_material_texture(state:&mut ProcessState, texture:&gltf::Texture, texcoord:u32) -> Result<MaterialTexture, Error> {
+    Ok(MaterialTexture {
+        texture_id: upload_texture(state, texture)?,
+        texcoord
+    })
+}
+
+//The texture manager de-dups across materials and resources (by image and sampler)
+//this just saves looking it up again for every material
+fn upload_texture(state:&mut ProcessState, texture:&gltf::Texture) -> Result<Id, Error> {
+    let texture_index = texture.index();
+
+    if state.uploads.texture_ids[texture_index].is_none() {
+        let image = &state.resource.images[texture.source().index()];
+        let sampler = get_sampler_options(&texture.sampler());
+
+        let texture_id = state.textures.create_image_texture(state.webgl, image, &sampler)?;
+
+        state.uploads.texture_ids[texture_index] = Some(texture_id);
+    }
+
+    Ok(state.uploads.texture_ids[texture_index].unwrap())
+}
+
+/// Filters that aren't set are left up to us, so they get the nicest option
+fn get_sampler_options(sampler:&gltf::texture::Sampler) -> SamplerOptions {
+    SamplerOptions {
+        min_filter: match sampler.min_filter() {
+            Some(GltfMinFilter::Nearest) => MinFilter::Nearest,
+            Some(GltfMinFilter::Linear) => MinFilter::Linear,
+            Some(GltfMinFilter::NearestMipmapNearest) => MinFilter::NearestMipmapNearest,
+            Some(GltfMinFilter::LinearMipmapNearest) => MinFilter::LinearMipmapNearest,
+            Some(GltfMinFilter::NearestMipmapLinear) => MinFilter::NearestMipmapLinear,
+            Some(GltfMinFilter::LinearMipmapLinear) | None => MinFilter::LinearMipmapLinear,
+        },
+        mag_filter: match sampler.mag_filter() {
+            Some(GltfMagFilter::Nearest) => MagFilter::Nearest,
+            Some(GltfMagFilter::Linear) | None => MagFilter::Linear,
+        },
+        wrap_s: get_wrap_mode(sampler.wrap_s()),
+        wrap_t: get_wrap_mode(sampler.wrap_t()),
+    }
+}
+
+fn get_wrap_mode(mode:WrappingMode) -> WrapMode {
+    match mode {
+        WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        WrappingMode::Repeat => WrapMode::Repeat,
+    }
 }
-*/
\ No newline at end of file
@@ -1,5 +1,12 @@
 pub mod loader;
+pub mod fetcher;
+pub mod data_uri;
+pub mod progress;
+pub mod cache;
 mod accessors;
+pub mod reader;
 mod buffer_view;
 mod materials;
-pub(crate) mod processor;
\ No newline at end of file
+pub(crate) mod processor;
+
+pub use self::processor::{GltfSceneKeys, get_attribute_name};
\ No newline at end of file
@@ -1,9 +1,15 @@
 use crate::errors::{Error, NativeError};
 use crate::gltf::loader::{GltfResource};
 use crate::primitives::*;
-use crate::shaders::compile_shader;
-use super::accessors::AccessorInfo;
+use crate::shaders::{compile_shader, get_attribute_location, ShaderCache, ShaderSettings};
+use crate::textures::TextureManager;
+use crate::materials::PbrMaterial;
+use crate::lights::Light;
+use crate::camera::CameraLens;
+use super::materials::get_material;
+use super::accessors::{AccessorInfo, get_accessor_data, get_attribute_options};
 use crate::nodes::*;
+use crate::transform::{Vector3, Quaternion};
 use shipyard::prelude::*;
 use awsm_web::webgl::{ 
     Id, 
@@ -11,102 +17,234 @@ use awsm_web::webgl::{
     BufferData,
     BufferTarget,
     BufferUsage,
-    AttributeOptions,
-    VertexArray,
     BeginMode
 };
 use std::convert::TryInto;
+use std::collections::HashMap;
 
 pub struct ProcessState <'a> {
     pub resource:&'a GltfResource,
     pub world:&'a mut World,
     pub webgl:&'a mut WebGl2Renderer,
-
-    //Just a local holder to help de-dup data
-    buffer_view_ids:Vec<Option<Id>>,
+    pub uploads:&'a mut GltfUploads,
+    //vao -> the buffers it uses (so they can be released later)
+    pub vertex_arrays:&'a mut VertexArrays,
+    pub shader_cache:&'a mut ShaderCache,
+    pub textures:&'a mut TextureManager,
 }
 
 impl <'a> ProcessState<'a> {
-    pub fn new(resource:&'a GltfResource, world:&'a mut World, webgl:&'a mut WebGl2Renderer) -> Self {
-        let buffer_view_ids:Vec<Option<Id>> = vec![None;resource.gltf.views().len()];
-
+    pub fn new(resource:&'a GltfResource, world:&'a mut World, webgl:&'a mut WebGl2Renderer, uploads:&'a mut GltfUploads, vertex_arrays:&'a mut VertexArrays, shader_cache:&'a mut ShaderCache, textures:&'a mut TextureManager) -> Self {
         Self{
             resource,
             world,
             webgl,
-            buffer_view_ids
+            uploads,
+            vertex_arrays,
+            shader_cache,
+            textures,
         }
     }
 }
 
-pub fn process_scene(state:ProcessState, scene:&gltf::scene::Scene) -> Result<(), Error> {
+/// Everything that's been uploaded to the GPU for a given resource
+/// The renderer can keep this around to reuse it when the same resource is uploaded again
+pub struct GltfUploads {
+    //Just a local holder to help de-dup data
+    buffer_view_ids:Vec<Option<Id>>,
+    //Sparse accessors get their own buffer
+    accessor_ids:Vec<Option<Id>>,
+    //The primitives for each mesh (i.e. shader and vao), in order
+    mesh_primitives:Vec<Option<Vec<Primitive>>>,
+    //one use of each texture, released along with the uploads
+    pub(crate) texture_ids:Vec<Option<Id>>,
+    pub(crate) materials:Vec<Option<PbrMaterial>>,
+}
+
+impl GltfUploads {
+    pub fn new(resource:&GltfResource) -> Self {
+        Self {
+            buffer_view_ids: vec![None;resource.gltf.views().len()],
+            accessor_ids: vec![None;resource.gltf.accessors().len()],
+            mesh_primitives: vec![None;resource.gltf.meshes().len()],
+            texture_ids: vec![None;resource.gltf.textures().len()],
+            materials: vec![None;resource.gltf.materials().len()],
+        }
+    }
+
+    pub fn mesh_primitives(&self) -> impl Iterator<Item = &Primitive> {
+        self.mesh_primitives
+            .iter()
+            .flatten()
+            .flatten()
+    }
+
+    pub fn uses_vertex_array(&self, vao_id:Id) -> bool {
+        self.mesh_primitives().any(|primitive| primitive.vao_id == vao_id)
+    }
+}
+
+/// The nodes that were created for a gltf scene
+pub struct GltfSceneKeys {
+    /// the top-level nodes of the scene
+    pub roots: Vec<EntityId>,
+    /// gltf node index -> ECS node
+    pub nodes: HashMap<usize, EntityId>,
+    /// the nodes with a camera, in scene order
+    /// pass one to Renderer::set_active_camera() to view through it
+    pub cameras: Vec<EntityId>,
+}
+
+pub fn process_scene(state:ProcessState, scene:&gltf::scene::Scene) -> Result<GltfSceneKeys, Error> {
     let mut state = state;
+    let mut keys = GltfSceneKeys {
+        roots: Vec::new(),
+        nodes: HashMap::new(),
+        cameras: Vec::new(),
+    };
 
-    fn traverse_node_root(state:&mut ProcessState, node:&gltf::Node) -> Result<(), Error> 
+    fn traverse_node(state:&mut ProcessState, keys:&mut GltfSceneKeys, node:&gltf::Node, parent:Option<EntityId>) -> Result<EntityId, Error> 
     {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let translation = Vector3::new(translation[0] as f64, translation[1] as f64, translation[2] as f64);
+        let rotation = Quaternion::new(rotation[0] as f64, rotation[1] as f64, rotation[2] as f64, rotation[3] as f64);
+        let scale = Vector3::new(scale[0] as f64, scale[1] as f64, scale[2] as f64);
+
+        let lens = node.camera().map(|camera| get_camera_lens(&camera));
+
+        let data = match &lens {
+            //the renderer sets the real aspect ratio after the upload
+            Some(lens) => NodeData::Camera(lens.get_projection(1.0)),
+            None => NodeData::Empty
+        };
+
+        let key = add_node(state.world, data, parent, Some(translation), Some(rotation), Some(scale))?;
+        keys.nodes.insert(node.index(), key);
+
+        if let Some(lens) = lens {
+            state.world.run::<(EntitiesMut, &mut CameraLens), _, _>(|(entities, mut lenses)| {
+                entities.add_component(&mut lenses, lens, key);
+            });
+            keys.cameras.push(key);
+        }
+
+        if let Some(name) = node.name() {
+            set_node_name(state.world, key, name);
+        }
+
+        if let Some(light) = node.light() {
+            process_light(state, &light, key);
+        }
         if let Some(mesh) = node.mesh() {
-            process_mesh(state, &mesh)?;
+            process_mesh(state, &mesh, key)?;
         }
-        for node in node.children() {
-            traverse_node_root(state, &node)?;
+        for child in node.children() {
+            traverse_node(state, keys, &child, Some(key))?;
         } 
-        Ok(())
-    };
+        Ok(key)
+    }
 
     for node in scene.nodes() {
-        traverse_node_root(&mut state, &node)?;
+        let key = traverse_node(&mut state, &mut keys, &node, None)?;
+        keys.roots.push(key);
     } 
+    Ok(keys)
+}
+
+fn get_camera_lens(camera:&gltf::Camera) -> CameraLens {
+    match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => CameraLens::Perspective {
+            yfov: perspective.yfov() as f64,
+            znear: perspective.znear() as f64,
+            zfar: perspective.zfar().map(|zfar| zfar as f64),
+        },
+        gltf::camera::Projection::Orthographic(orthographic) => CameraLens::Orthographic {
+            xmag: orthographic.xmag() as f64,
+            ymag: orthographic.ymag() as f64,
+            znear: orthographic.znear() as f64,
+            zfar: orthographic.zfar() as f64,
+        },
+    }
+}
+
+/// The light is added as a component on the node itself
+pub fn process_light(state:&mut ProcessState, light:&gltf::khr_lights_punctual::Light, key:EntityId) {
+    let color = light.color();
+    let intensity = light.intensity();
+    let range = light.range();
+
+    let light = match light.kind() {
+        gltf::khr_lights_punctual::Kind::Directional => Light::Directional { color, intensity },
+        gltf::khr_lights_punctual::Kind::Point => Light::Point { color, intensity, range },
+        gltf::khr_lights_punctual::Kind::Spot { inner_cone_angle, outer_cone_angle } => Light::Spot { color, intensity, range, inner_cone_angle, outer_cone_angle },
+    };
+
+    state.world.run::<(EntitiesMut, &mut Light), _, _>(|(entities, mut lights)| {
+        entities.add_component(&mut lights, light, key);
+    });
+}
+
+/// Each primitive is added as a child of the mesh's node
+pub fn process_mesh(state:&mut ProcessState, mesh:&gltf::mesh::Mesh, parent:EntityId) -> Result<(), Error> {
+
+    let mesh_id = mesh.index();
+
+    if state.uploads.mesh_primitives[mesh_id].is_none() {
+        state.uploads.mesh_primitives[mesh_id] = Some(upload_mesh(state, mesh)?);
+    }
+
+    let primitives = state.uploads.mesh_primitives[mesh_id].as_ref().unwrap().clone();
+
+    //same order as when they were uploaded
+    for (primitive, gltf_primitive) in primitives.into_iter().zip(mesh.primitives()) {
+        let material = get_material(state, &gltf_primitive.material())?;
+        //the uploads hold their own use, this one is released along with the node
+        for texture_id in material.texture_ids() {
+            state.textures.retain_texture(texture_id)?;
+        }
+
+        let key = add_node(state.world, NodeData::Primitive(primitive), Some(parent), None, None, None)?;
+        if let Some(name) = mesh.name() {
+            set_node_name(state.world, key, name);
+        }
+
+        state.world.run::<(EntitiesMut, &mut PbrMaterial), _, _>(|(entities, mut materials)| {
+            entities.add_component(&mut materials, material, key);
+        });
+    }
+
     Ok(())
 }
 
-pub fn process_mesh(state:&mut ProcessState, mesh:&gltf::mesh::Mesh) -> Result<(), Error> {
+fn upload_mesh(state:&mut ProcessState, mesh:&gltf::mesh::Mesh) -> Result<Vec<Primitive>, Error> {
+
+    let mut primitives = Vec::with_capacity(mesh.primitives().len());
 
     for primitive in mesh.primitives() {
 
+        //checked before anything is uploaded for it
+        let draw_info = get_draw_info(&primitive)?;
 
-        let shader_id = compile_shader(state.webgl)?;
+        let shader_settings = get_shader_settings(&primitive);
+        let shader_id = compile_shader(state.webgl, state.shader_cache, &shader_settings)?;
 
-        let vao_id = state.webgl.create_vertex_array()?;
+        let vao_id = state.vertex_arrays.create(state.webgl)?;
         //Probably some way of making this just one iterator that exists early...
         let mut attributes = Vec::with_capacity(primitive.attributes().len());
 
-        for (semantic, accessor) in primitive.attributes() {
+        for (location, accessor) in get_vertex_attributes(&primitive) {
             let buffer_id = upload_accessor(state, &accessor, BufferTarget::ArrayBuffer)?;
             let accessor_info = AccessorInfo::new(&accessor);
-            //TODO - figure out wtf this should really be... 
-            //OPTION 1: seems broken let opts = AttributeOptions::new(accessor_info.data_size, accessor_info.webgl_data_type);
-            let opts = AttributeOptions::new(accessor.count().try_into().unwrap(), accessor_info.webgl_data_type);
-            let attribute_name = match semantic {
-                gltf::Semantic::Positions =>  "a_position",
-                gltf::Semantic::Normals => "a_normal",
-                gltf::Semantic::Tangents => "a_tangent",
-                gltf::Semantic::Colors(_color) => "colors",
-                gltf::Semantic::TexCoords(_coord) => "texcoords",
-                gltf::Semantic::Joints(_joints) => "joints",
-                gltf::Semantic::Weights(_weights) => "weights",
-                gltf::Semantic::Extras(_extras) => "extras",
-            };
-
-            //log::info!("dimensions for {} is {}", attribute_name, accessor_info.dim_size);
-            //log::info!("attribute {} data buffer id is {:?} for accessor {}, primitive {}, count {}", attribute_name, buffer_id, accessor.index(), primitive.index(), accessor.count());
-            attributes.push((buffer_id, attribute_name, accessor_info, opts));
+            let opts = get_attribute_options(&accessor, &accessor_info)?;
+
+            attributes.push(VertexAttribute { location, buffer_id, opts });
         }
 
-        let draw_mode = get_primitive_mode(&primitive);
-        let (elements_id, draw_info) = match primitive.indices() {
-            Some(accessor) => {
-                let accessor_info = AccessorInfo::new(&accessor);
-                let buffer_id = upload_accessor(state, &accessor, BufferTarget::ElementArrayBuffer)?;
-                //log::info!("elements data buffer id is {:?} for accessor {}, primitive {}, count {}", buffer_id, accessor.index(), primitive.index(), accessor.count());
-                //TODO - figure out wtf this should really be... 
-                (Some(buffer_id), PrimitiveDraw::Elements(draw_mode, accessor.count().try_into().unwrap(), accessor_info.webgl_data_type, accessor.offset().try_into().unwrap()))
-            },
-
-            //TODO
-            None => (None, PrimitiveDraw::Direct(draw_mode, 36, 0))
+        let elements_id = match primitive.indices() {
+            Some(accessor) => Some(upload_accessor(state, &accessor, BufferTarget::ElementArrayBuffer)?),
+            None => None
         };
 
-
         /*
             Ideas: 
             1. We have info on the semantics in attributes - could use that here...
@@ -115,36 +253,109 @@ pub fn process_mesh(state:&mut ProcessState, mesh:&gltf::mesh::Mesh) -> Result<(
         */
 
 
-        let vertex_arrays = attributes
-            .iter()
-            .map(|(buffer_id, attribute_name, _dim_size, opts)| {
-                VertexArray{
-                    attribute_name,
-                    buffer_id: *buffer_id,
-                    opts: &opts
-                }
-            })
-            .collect::<Vec<VertexArray>>();
-
-        if vertex_arrays.len() != attributes.iter().len() {
-            return Err("lengths don't match".into());
-        }
-        if vertex_arrays.len() == 0 {
-            return Err("no elements!".into());
+        state.vertex_arrays.assign(state.webgl, vao_id, elements_id, &attributes)?;
+
+        primitives.push(Primitive{shader_id, vao_id, draw_info, });
+    }
+
+    Ok(primitives)
+}
+
+/// The attributes that are bound to the vao, with their location
+/// Anything that no shader can read (e.g. COLOR_1 or a custom _FOO attribute) is skipped
+/// rather than failing to find it in the program
+fn get_vertex_attributes<'a>(primitive:&gltf::mesh::Primitive<'a>) -> Vec<(u32, gltf::Accessor<'a>)> {
+    primitive
+        .attributes()
+        .filter_map(|(semantic, accessor)| {
+            get_attribute_location(&get_attribute_name(&semantic)).map(|location| (location, accessor))
+        })
+        .collect()
+}
+
+/// Indexed primitives draw every index, the others every vertex
+/// The POSITION count is checked either way, since it's what the indices point into
+fn get_draw_info(primitive:&gltf::mesh::Primitive) -> Result<PrimitiveDraw, Error> {
+    let vertex_count = get_vertex_count(primitive)?;
+    let draw_mode = get_primitive_mode(primitive);
+
+    Ok(match primitive.indices() {
+        Some(accessor) => {
+            let accessor_info = AccessorInfo::new(&accessor);
+            //sparse accessors are uploaded on their own, so there's no offset
+            let offset = if accessor.sparse().is_some() { 0 } else { accessor.offset() };
+            PrimitiveDraw::Elements(draw_mode, accessor.count().try_into().unwrap(), accessor_info.webgl_data_type, offset.try_into().unwrap())
+        },
+
+        None => PrimitiveDraw::Direct(draw_mode, vertex_count.try_into().unwrap(), 0)
+    })
+}
+
+/// The number of vertices, from the POSITION accessor
+/// Every attribute must have the same count
+fn get_vertex_count(primitive:&gltf::mesh::Primitive) -> Result<usize, Error> {
+    let count = primitive
+        .get(&gltf::Semantic::Positions)
+        .map(|accessor| accessor.count())
+        .ok_or(NativeError::AttributePositionMissing(primitive.index()))?;
+
+    for (semantic, accessor) in primitive.attributes() {
+        if accessor.count() != count {
+            return Err(NativeError::AttributeCount(get_attribute_name(&semantic), count, accessor.count()).into());
         }
+    }
 
-        log::info!("{:?}", &vertex_arrays);
+    Ok(count)
+}
 
-        state.webgl.assign_vertex_array( 
-            vao_id, 
-            elements_id, 
-            &vertex_arrays
-        )?;
+fn get_shader_settings(primitive:&gltf::mesh::Primitive) -> ShaderSettings {
+    let mut settings = ShaderSettings::default();
 
-        add_node(state.world, NodeData::Primitive(Primitive{shader_id, vao_id, draw_info, }), None, None, None, None);
+    //same as what's bound to the vao, e.g. COLOR_1 doesn't count
+    for (semantic, _) in primitive.attributes() {
+        if get_attribute_location(&get_attribute_name(&semantic)).is_none() {
+            continue;
+        }
+        match semantic {
+            gltf::Semantic::Positions => settings.has_position = true,
+            gltf::Semantic::Normals => settings.has_normal = true,
+            gltf::Semantic::Tangents => settings.has_tangent = true,
+            gltf::Semantic::Colors(set) => settings.n_colors = settings.n_colors.max(set + 1),
+            gltf::Semantic::TexCoords(set) => settings.n_texcoords = settings.n_texcoords.max(set + 1),
+            //weights come in the same sets
+            gltf::Semantic::Joints(set) => settings.n_joint_sets = settings.n_joint_sets.max(set + 1),
+            gltf::Semantic::Weights(_) | gltf::Semantic::Extras(_) => {}
+        }
     }
 
-    Ok(())
+    settings.n_morph_targets = primitive.morph_targets().count() as u32;
+    //settings.max_joints stays 0 until there are joint matrices to upload
+
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    settings.has_base_color_texture = pbr.base_color_texture().is_some();
+    settings.has_metallic_roughness_texture = pbr.metallic_roughness_texture().is_some();
+    settings.has_normal_texture = material.normal_texture().is_some();
+    settings.has_occlusion_texture = material.occlusion_texture().is_some();
+    settings.has_emissive_texture = material.emissive_texture().is_some();
+    settings.alpha_mask = material.alpha_mode() == gltf::material::AlphaMode::Mask;
+
+    settings
+}
+
+/// One attribute per set, e.g. TEXCOORD_1 is a_texcoord_1
+/// Custom attributes are lowercased, e.g. _FOO is a_foo
+pub fn get_attribute_name(semantic:&gltf::Semantic) -> String {
+    match semantic {
+        gltf::Semantic::Positions => "a_position".to_string(),
+        gltf::Semantic::Normals => "a_normal".to_string(),
+        gltf::Semantic::Tangents => "a_tangent".to_string(),
+        gltf::Semantic::Colors(set) => format!("a_color_{}", set),
+        gltf::Semantic::TexCoords(set) => format!("a_texcoord_{}", set),
+        gltf::Semantic::Joints(set) => format!("a_joints_{}", set),
+        gltf::Semantic::Weights(set) => format!("a_weights_{}", set),
+        gltf::Semantic::Extras(name) => format!("a_{}", name.to_lowercase()),
+    }
 }
 
 fn get_primitive_mode(primitive:&gltf::mesh::Primitive) -> BeginMode {
@@ -166,21 +377,7 @@ fn upload_accessor(state:&mut ProcessState, accessor:&gltf::accessor::Accessor,
 
     match accessor.sparse() {
         Some(_sparse) => {
-            match accessor.view() {
-                Some(_view) => {
-                    //TODO
-                    log::info!("get the typed data from buffer view");
-                },
-                None => {
-                    //TODO
-                    log::info!("create empty (filled with 0's) typed data from buffer view");
-                }
-            }
-
-            //TODO
-            log::info!("replace typed data with sparse info");
-
-            Err(NativeError::Wip.into())
+            upload_sparse_accessor(state, accessor, target)
         },
         None => {
             let view = accessor.view().ok_or(Error::from(NativeError::AccessorView))?;
@@ -189,19 +386,49 @@ fn upload_accessor(state:&mut ProcessState, accessor:&gltf::accessor::Accessor,
     }
 }
 
+//Upload the sparse accessor if and only if there isn't already an id for that specific accessor
+//The uploaded data is the base data (or 0's) with the sparse values substituted in
+//In either case, return the Id
+fn upload_sparse_accessor(state:&mut ProcessState, accessor:&gltf::accessor::Accessor, target:BufferTarget) -> Result<Id, Error> {
+
+    let ProcessState {webgl, resource, uploads, ..} = state;
+    let GltfResource {buffers, ..} = resource; 
+    let GltfUploads {accessor_ids, ..} = uploads;
+
+    let accessor_id = accessor.index();
+
+    if accessor_ids[accessor_id].is_none() {
+
+        let buffer_id = webgl.create_buffer()?;
+        let raw_data = get_accessor_data(accessor, buffers)?;
+
+        let data = BufferData::new(
+            &*raw_data,
+            target,
+            BufferUsage::StaticDraw
+        );
+
+        webgl.upload_buffer(buffer_id, data)?;
+        accessor_ids[accessor_id] = Some(buffer_id);
+    }
+
+    Ok(accessor_ids[accessor_id].unwrap())
+}
+
 //Upload the buffer view if and only if there isn't already an id for that specific view
 //In either case, return the Id
 fn upload_buffer_view(state:&mut ProcessState, view:&gltf::buffer::View, target:BufferTarget) -> Result<Id, Error> {
 
-    let ProcessState {webgl, resource, buffer_view_ids, ..} = state;
+    let ProcessState {webgl, resource, uploads, ..} = state;
     let GltfResource {buffers, ..} = resource; 
+    let GltfUploads {buffer_view_ids, ..} = uploads;
 
     let buffer_view_id = view.index();
 
     if buffer_view_ids[buffer_view_id].is_none() {
 
         let buffer_id = webgl.create_buffer()?;
-        let raw_data = super::buffer_view::get_buffer_view_data(&view, &buffers);
+        let raw_data = super::buffer_view::get_buffer_view_data(view, buffers);
 
         let data = BufferData::new(
             &raw_data,
@@ -216,3 +443,123 @@ fn upload_buffer_view(state:&mut ProcessState, view:&gltf::buffer::View, target:
     Ok(buffer_view_ids[buffer_view_id].unwrap())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use awsm_web::webgl::DataType;
+
+    //one mesh with one primitive, every accessor is a view of the same (unloaded) buffer
+    fn get_document(attributes:&str, indices:Option<usize>, accessors:&str) -> gltf::Document {
+        gltf::Gltf::from_slice(get_json(attributes, indices, accessors).as_bytes()).unwrap().document
+    }
+
+    fn get_json(attributes:&str, indices:Option<usize>, accessors:&str) -> String {
+        let indices = indices.map(|index| format!(r#", "indices": {}"#, index)).unwrap_or_default();
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": 1024, "uri": "test.bin" }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": 1024 }}],
+            "accessors": [{}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ {} }}{} }}] }}]
+        }}"#, accessors, attributes, indices)
+    }
+
+    fn get_accessor(component_type:u32, count:usize, type_:&str) -> String {
+        format!(r#"{{ "bufferView": 0, "componentType": {}, "count": {}, "type": "{}" }}"#, component_type, count, type_)
+    }
+
+    fn get_position_accessor(count:usize) -> String {
+        format!(r#"{{ "bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 1] }}"#, count)
+    }
+
+    fn get_primitive(document:&gltf::Document) -> gltf::mesh::Primitive<'_> {
+        document.meshes().next().unwrap().primitives().next().unwrap()
+    }
+
+    #[test]
+    fn skinned_primitive_binds_what_shaders_read() {
+        let accessors = [
+            get_position_accessor(3),
+            get_accessor(5126, 3, "VEC3"),
+            get_accessor(5121, 3, "VEC4"),
+            get_accessor(5126, 3, "VEC4"),
+            get_accessor(5126, 3, "VEC4"),
+            get_accessor(5126, 3, "VEC2"),
+            get_accessor(5126, 3, "SCALAR"),
+        ].join(",");
+        let document = get_document(
+            r#""POSITION": 0, "NORMAL": 1, "JOINTS_0": 2, "WEIGHTS_0": 3, "COLOR_1": 4, "TEXCOORD_2": 5, "_TEMPERATURE": 6"#,
+            None,
+            &accessors
+        );
+        let primitive = get_primitive(&document);
+
+        let mut attributes:Vec<(u32, usize)> = get_vertex_attributes(&primitive)
+            .iter()
+            .map(|(location, accessor)| (*location, accessor.index()))
+            .collect();
+        attributes.sort();
+
+        assert_eq!(attributes, vec![(0, 0), (1, 1), (6, 2), (7, 3)]);
+
+        //and everything that is bound has valid options
+        for (_, accessor) in get_vertex_attributes(&primitive) {
+            get_attribute_options(&accessor, &AccessorInfo::new(&accessor)).unwrap();
+        }
+        assert_eq!(get_vertex_count(&primitive).unwrap(), 3);
+
+        //the joints are declared, but there's no skin to use them with yet
+        let settings = get_shader_settings(&primitive);
+        assert_eq!(settings.n_joint_sets, 1);
+        assert_eq!(settings.n_colors, 0);
+        assert_eq!(settings.n_texcoords, 0);
+        assert!(!settings.get_defines().contains(&"#define HAS_SKIN".to_string()));
+    }
+
+    #[test]
+    fn vertex_count_without_indices() {
+        let accessors = [get_position_accessor(6), get_accessor(5126, 6, "VEC3")].join(",");
+        let document = get_document(r#""POSITION": 0, "NORMAL": 1"#, None, &accessors);
+        let primitive = get_primitive(&document);
+
+        assert_eq!(get_vertex_count(&primitive).unwrap(), 6);
+        assert!(matches!(get_draw_info(&primitive).unwrap(), PrimitiveDraw::Direct(BeginMode::Triangles, 6, 0)));
+    }
+
+    #[test]
+    fn vertex_count_with_indices() {
+        let accessors = [get_position_accessor(4), get_accessor(5123, 6, "SCALAR")].join(",");
+        let document = get_document(r#""POSITION": 0"#, Some(1), &accessors);
+        let primitive = get_primitive(&document);
+
+        //the vertices, not the indices
+        assert_eq!(get_vertex_count(&primitive).unwrap(), 4);
+        //but every index is drawn
+        assert!(matches!(get_draw_info(&primitive).unwrap(), PrimitiveDraw::Elements(BeginMode::Triangles, 6, DataType::UnsignedShort, 0)));
+    }
+
+    #[test]
+    fn vertex_count_without_position() {
+        //validation already rejects it when loading, but the document can also be built without
+        let accessors = [get_accessor(5126, 3, "VEC3"), get_accessor(5123, 3, "SCALAR")].join(",");
+        let json = get_json(r#""NORMAL": 0"#, Some(1), &accessors);
+        let document = gltf::Gltf::from_slice_without_validation(json.as_bytes()).unwrap().document;
+        let primitive = get_primitive(&document);
+
+        assert!(matches!(get_vertex_count(&primitive), Err(Error::Native(NativeError::AttributePositionMissing(0)))));
+        assert!(get_draw_info(&primitive).is_err());
+    }
+
+    #[test]
+    fn vertex_count_mismatch() {
+        let accessors = [get_position_accessor(3), get_accessor(5126, 4, "VEC3")].join(",");
+        let document = get_document(r#""POSITION": 0, "NORMAL": 1"#, None, &accessors);
+        let primitive = get_primitive(&document);
+
+        match get_vertex_count(&primitive) {
+            Err(Error::Native(NativeError::AttributeCount(name, 3, 4))) => assert_eq!(name, "a_normal"),
+            _ => panic!("expected a count mismatch")
+        }
+    }
+}
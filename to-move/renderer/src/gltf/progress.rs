@@ -0,0 +1,127 @@
+use crate::errors::{Error, NativeError};
+use super::data_uri::DataUri;
+use gltf::{Document, buffer, image};
+use futures::Future;
+use futures::future::{abortable, AbortHandle, TryFutureExt};
+use std::cell::RefCell;
+
+pub type ProgressCallback = Box<dyn Fn(&LoadProgress)>;
+
+/// Sent to the progress callback when the load starts, when the gltf/glb file itself
+/// has been fetched, and whenever a buffer or image finishes loading
+/// Bytes are counted as they are fetched, so embedded data (data uris, the glb bin chunk,
+/// images in buffer views) is part of the file it's embedded in rather than counted again
+/// bytes_total is None until the file is parsed, and stays None if there are external images
+/// since their size isn't known up front
+#[derive(Clone, Debug, Default)]
+pub struct LoadProgress {
+    pub file_loaded: bool,
+    pub buffers_loaded: usize,
+    pub buffers_total: usize,
+    pub images_loaded: usize,
+    pub images_total: usize,
+    pub bytes_loaded: usize,
+    pub bytes_total: Option<usize>,
+}
+
+impl LoadProgress {
+    pub fn items_loaded(&self) -> usize {
+        self.buffers_loaded + self.images_loaded
+    }
+
+    pub fn items_total(&self) -> usize {
+        self.buffers_total + self.images_total
+    }
+}
+
+pub(crate) struct ProgressTracker {
+    progress: RefCell<LoadProgress>,
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressTracker {
+    pub fn new(callback: Option<ProgressCallback>) -> Self {
+        Self {
+            progress: RefCell::new(LoadProgress::default()),
+            callback
+        }
+    }
+
+    /// Reports that the load has begun, before anything is fetched
+    pub fn begin(&self) {
+        self.update(|_| {});
+    }
+
+    pub fn file_loaded(&self, byte_len: usize) {
+        self.update(|progress| {
+            progress.file_loaded = true;
+            progress.bytes_loaded += byte_len;
+        });
+    }
+
+    pub fn start(&self, document:&Document) {
+        let has_external_images = document.images().any(|image| match image.source() {
+            image::Source::Uri { uri, .. } => DataUri::parse(uri).is_none(),
+            image::Source::View { .. } => false,
+        });
+        let external_buffer_bytes:usize = document.buffers()
+            .filter(|buffer| match buffer.source() {
+                buffer::Source::Uri(uri) => DataUri::parse(uri).is_none(),
+                buffer::Source::Bin => false,
+            })
+            .map(|buffer| buffer.length())
+            .sum();
+
+        self.update(|progress| {
+            progress.buffers_total = document.buffers().len();
+            progress.images_total = document.images().len();
+            progress.bytes_total = if has_external_images { None } else { Some(progress.bytes_loaded + external_buffer_bytes) };
+        });
+    }
+
+    /// byte_len is what was fetched, i.e. 0 for embedded buffers
+    pub fn buffer_loaded(&self, byte_len: usize) {
+        self.update(|progress| {
+            progress.buffers_loaded += 1;
+            progress.bytes_loaded += byte_len;
+        });
+    }
+
+    pub fn image_loaded(&self) {
+        self.update(|progress| {
+            progress.images_loaded += 1;
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut LoadProgress)) {
+        let mut progress = self.progress.borrow_mut();
+        f(&mut progress);
+        if let Some(callback) = &self.callback {
+            callback(&progress);
+        }
+    }
+}
+
+/// Cancels the load it was created with
+/// Outstanding buffer and image futures are dropped
+/// and the load resolves with NativeError::Cancelled
+/// The underlying browser requests are not aborted (awsm's fetch has no AbortSignal support)
+/// they run to completion in the background and their results are discarded
+pub struct LoadCancel(AbortHandle);
+
+impl LoadCancel {
+    pub fn cancel(&self) {
+        self.0.abort();
+    }
+}
+
+/// Wraps any of the loader futures so that it can be cancelled
+pub fn cancellable<T>(future: impl Future<Output = Result<T, Error>>) -> (impl Future<Output = Result<T, Error>>, LoadCancel) {
+    let (future, handle) = abortable(future);
+
+    let future = future
+        .map_err(|_| Error::from(NativeError::Cancelled))
+        .and_then(|result| async move { result });
+
+    (future, LoadCancel(handle))
+}
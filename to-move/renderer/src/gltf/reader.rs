@@ -0,0 +1,345 @@
+use crate::errors::{Error, NativeError};
+use super::loader::GltfResource;
+use super::accessors::get_accessor_data;
+use gltf::accessor::{Accessor, DataType, Dimensions};
+use std::borrow::Cow;
+
+/// CPU-side typed access to accessor data (no GPU involved)
+/// byteOffset, byteStride and sparse substitution are all resolved up front
+/// so the data here is always tightly packed
+pub struct AccessorReader<'a> {
+    data: Cow<'a, [u8]>,
+    data_type: DataType,
+    dimensions: Dimensions,
+    normalized: bool,
+    count: usize,
+}
+
+impl <I> GltfResource<I> {
+    pub fn read_accessor<'a>(&'a self, accessor:&Accessor) -> Result<AccessorReader<'a>, Error> {
+        let data = get_accessor_data(accessor, &self.buffers)?;
+
+        Ok(AccessorReader {
+            data,
+            data_type: accessor.data_type(),
+            dimensions: accessor.dimensions(),
+            normalized: accessor.normalized(),
+            count: accessor.count(),
+        })
+    }
+}
+
+macro_rules! impl_read_array {
+    ( $( $name:ident, $dim:pat, $len:expr );* ) => {
+        $(
+            pub fn $name(&self) -> Result<Vec<[f32;$len]>, Error> {
+                match self.dimensions {
+                    $dim => {
+                        let values:Vec<f32> = self.iter_f32().collect();
+                        Ok(values
+                            .chunks_exact($len)
+                            .map(|chunk| {
+                                let mut arr = [0.0;$len];
+                                arr.copy_from_slice(chunk);
+                                arr
+                            })
+                            .collect())
+                    },
+                    _ => Err(NativeError::AccessorDimensions(stringify!($name)).into())
+                }
+            }
+        )*
+    };
+}
+
+impl <'a> AccessorReader<'a> {
+    /// number of elements (e.g. number of vec3's, not number of floats)
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    /// The raw tightly-packed bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Every component as f32
+    /// If the accessor is normalized, integer components are mapped to [0, 1] or [-1, 1]
+    /// Otherwise they are just casted
+    pub fn iter_f32(&self) -> impl Iterator<Item = f32> + '_ {
+        let data_type = self.data_type;
+        let normalized = self.normalized;
+        self.data
+            .chunks_exact(get_component_size(data_type))
+            .map(move |bytes| component_to_f32(data_type, normalized, bytes))
+    }
+
+    /// Every component as u32
+    /// Only valid for unsigned integer data (e.g. indices, joints)
+    pub fn iter_u32(&self) -> Result<impl Iterator<Item = u32> + '_, Error> {
+        let data_type = self.data_type;
+        match data_type {
+            DataType::U8 | DataType::U16 | DataType::U32 => {
+                Ok(self.data
+                    .chunks_exact(get_component_size(data_type))
+                    .map(move |bytes| component_to_u32(data_type, bytes)))
+            },
+            _ => Err(NativeError::AccessorDataType("u32").into())
+        }
+    }
+
+    pub fn read_f32(&self) -> Vec<f32> {
+        self.iter_f32().collect()
+    }
+
+    pub fn read_u32(&self) -> Result<Vec<u32>, Error> {
+        self.iter_u32().map(|iter| iter.collect())
+    }
+
+    pub fn read_u16(&self) -> Result<Vec<u16>, Error> {
+        match self.data_type {
+            DataType::U8 | DataType::U16 => {
+                self.iter_u32().map(|iter| iter.map(|n| n as u16).collect())
+            },
+            _ => Err(NativeError::AccessorDataType("u16").into())
+        }
+    }
+
+    pub fn read_u8(&self) -> Result<Vec<u8>, Error> {
+        match self.data_type {
+            DataType::U8 => Ok(self.data.to_vec()),
+            _ => Err(NativeError::AccessorDataType("u8").into())
+        }
+    }
+
+    impl_read_array!(
+        read_vec2, Dimensions::Vec2, 2;
+        read_vec3, Dimensions::Vec3, 3;
+        read_vec4, Dimensions::Vec4, 4;
+        read_mat4, Dimensions::Mat4, 16
+    );
+}
+
+fn get_component_size(data_type:DataType) -> usize {
+    match data_type {
+        DataType::I8 | DataType::U8 => 1,
+        DataType::I16 | DataType::U16 => 2,
+        DataType::U32 | DataType::F32 => 4,
+    }
+}
+
+fn component_to_u32(data_type:DataType, bytes:&[u8]) -> u32 {
+    match data_type {
+        DataType::U8 => bytes[0] as u32,
+        DataType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+        DataType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        _ => unreachable!()
+    }
+}
+
+//See "Animations" in the glTF spec for the normalization equations
+fn component_to_f32(data_type:DataType, normalized: bool, bytes:&[u8]) -> f32 {
+    match data_type {
+        DataType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        DataType::I8 => {
+            let n = bytes[0] as i8;
+            if normalized { (n as f32 / 127.0).max(-1.0) } else { n as f32 }
+        },
+        DataType::U8 => {
+            let n = bytes[0];
+            if normalized { n as f32 / 255.0 } else { n as f32 }
+        },
+        DataType::I16 => {
+            let n = i16::from_le_bytes([bytes[0], bytes[1]]);
+            if normalized { (n as f32 / 32767.0).max(-1.0) } else { n as f32 }
+        },
+        DataType::U16 => {
+            let n = u16::from_le_bytes([bytes[0], bytes[1]]);
+            if normalized { n as f32 / 65535.0 } else { n as f32 }
+        },
+        //normalized u32 isn't allowed by the spec
+        DataType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the accessors all read from a single buffer view over the whole buffer
+    fn get_resource(buffer:Vec<u8>, accessors:&[&str]) -> GltfResource<()> {
+        let json = format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": {len}, "uri": "test.bin" }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": {len} }}],
+            "accessors": [{accessors}]
+        }}"#, len = buffer.len(), accessors = accessors.join(","));
+
+        GltfResource {
+            gltf: gltf::Gltf::from_slice(json.as_bytes()).unwrap().document,
+            buffers: vec![buffer],
+            images: Vec::new(),
+        }
+    }
+
+    fn get_accessor(resource:&GltfResource<()>, index:usize) -> Accessor<'_> {
+        resource.gltf.accessors().nth(index).unwrap()
+    }
+
+    fn scalar(component_type:u32, count:usize, normalized:bool) -> String {
+        format!(r#"{{ "bufferView": 0, "componentType": {}, "count": {}, "type": "SCALAR", "normalized": {} }}"#, component_type, count, normalized)
+    }
+
+    fn f32_bytes(values:&[f32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    fn read_f32(buffer:Vec<u8>, component_type:u32, count:usize, normalized:bool) -> Vec<f32> {
+        let resource = get_resource(buffer, &[&scalar(component_type, count, normalized)]);
+        resource.read_accessor(&get_accessor(&resource, 0)).unwrap().read_f32()
+    }
+
+    #[test]
+    fn f32_components() {
+        assert_eq!(read_f32(f32_bytes(&[1.5, -2.0]), 5126, 2, false), vec![1.5, -2.0]);
+    }
+
+    #[test]
+    fn i8_components() {
+        let bytes = vec![127, 0, (-64i8) as u8, (-128i8) as u8];
+
+        assert_eq!(read_f32(bytes.clone(), 5120, 4, false), vec![127.0, 0.0, -64.0, -128.0]);
+        //-128 / 127 is just past -1, so it's clamped
+        assert_eq!(read_f32(bytes, 5120, 4, true), vec![1.0, 0.0, -64.0 / 127.0, -1.0]);
+    }
+
+    #[test]
+    fn u8_components() {
+        let bytes = vec![255, 0, 51, 0];
+
+        assert_eq!(read_f32(bytes.clone(), 5121, 3, false), vec![255.0, 0.0, 51.0]);
+        assert_eq!(read_f32(bytes, 5121, 3, true), vec![1.0, 0.0, 0.2]);
+    }
+
+    #[test]
+    fn i16_components() {
+        let bytes:Vec<u8> = [32767i16, -32768, 0, 0].iter().flat_map(|n| n.to_le_bytes().to_vec()).collect();
+
+        assert_eq!(read_f32(bytes.clone(), 5122, 3, false), vec![32767.0, -32768.0, 0.0]);
+        assert_eq!(read_f32(bytes, 5122, 3, true), vec![1.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn u16_components() {
+        let bytes:Vec<u8> = [65535u16, 0].iter().flat_map(|n| n.to_le_bytes().to_vec()).collect();
+
+        assert_eq!(read_f32(bytes.clone(), 5123, 2, false), vec![65535.0, 0.0]);
+        assert_eq!(read_f32(bytes, 5123, 2, true), vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn u32_components() {
+        let bytes:Vec<u8> = [7u32, 70000].iter().flat_map(|n| n.to_le_bytes().to_vec()).collect();
+        let resource = get_resource(bytes, &[&scalar(5125, 2, false)]);
+        let reader = resource.read_accessor(&get_accessor(&resource, 0)).unwrap();
+
+        assert_eq!(reader.read_f32(), vec![7.0, 70000.0]);
+        assert_eq!(reader.read_u32().unwrap(), vec![7, 70000]);
+    }
+
+    #[test]
+    fn unsigned_integer_reads() {
+        let resource = get_resource(vec![1, 2, 3, 4], &[&scalar(5121, 4, false), &scalar(5123, 2, false)]);
+        let bytes = resource.read_accessor(&get_accessor(&resource, 0)).unwrap();
+        let shorts = resource.read_accessor(&get_accessor(&resource, 1)).unwrap();
+
+        assert_eq!(bytes.read_u8().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(bytes.read_u16().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(bytes.read_u32().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(shorts.read_u16().unwrap(), vec![0x0201, 0x0403]);
+        assert_eq!(shorts.read_u32().unwrap(), vec![0x0201, 0x0403]);
+    }
+
+    #[test]
+    fn wrong_data_type() {
+        let resource = get_resource(f32_bytes(&[1.0, 2.0]), &[&scalar(5126, 2, false), &scalar(5125, 2, false), &scalar(5123, 4, false)]);
+        let floats = resource.read_accessor(&get_accessor(&resource, 0)).unwrap();
+        let ints = resource.read_accessor(&get_accessor(&resource, 1)).unwrap();
+        let shorts = resource.read_accessor(&get_accessor(&resource, 2)).unwrap();
+
+        assert!(matches!(floats.read_u32(), Err(Error::Native(NativeError::AccessorDataType("u32")))));
+        assert!(matches!(ints.read_u16(), Err(Error::Native(NativeError::AccessorDataType("u16")))));
+        assert!(matches!(shorts.read_u8(), Err(Error::Native(NativeError::AccessorDataType("u8")))));
+    }
+
+    #[test]
+    fn arrays() {
+        let values:Vec<f32> = (0..16).map(|n| n as f32).collect();
+        let resource = get_resource(f32_bytes(&values), &[
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 8, "type": "VEC2" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC4" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 1, "type": "MAT4" }"#,
+        ]);
+        let read = |index| resource.read_accessor(&get_accessor(&resource, index)).unwrap();
+
+        assert_eq!(read(0).read_vec2().unwrap()[1], [2.0, 3.0]);
+        assert_eq!(read(1).read_vec3().unwrap(), vec![[0.0, 1.0, 2.0], [3.0, 4.0, 5.0], [6.0, 7.0, 8.0], [9.0, 10.0, 11.0]]);
+        assert_eq!(read(2).read_vec4().unwrap()[3], [12.0, 13.0, 14.0, 15.0]);
+        let mut mat4 = [0.0;16];
+        mat4.copy_from_slice(&values);
+        assert_eq!(read(3).read_mat4().unwrap(), vec![mat4]);
+    }
+
+    #[test]
+    fn arrays_check_dimensions() {
+        let resource = get_resource(f32_bytes(&[0.0;16]), &[
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 8, "type": "VEC2" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 1, "type": "MAT4" }"#,
+        ]);
+        let vec2 = resource.read_accessor(&get_accessor(&resource, 0)).unwrap();
+        let mat4 = resource.read_accessor(&get_accessor(&resource, 1)).unwrap();
+
+        assert!(matches!(vec2.read_vec3(), Err(Error::Native(NativeError::AccessorDimensions("read_vec3")))));
+        assert!(matches!(vec2.read_vec4(), Err(Error::Native(NativeError::AccessorDimensions("read_vec4")))));
+        assert!(matches!(vec2.read_mat4(), Err(Error::Native(NativeError::AccessorDimensions("read_mat4")))));
+        assert!(matches!(mat4.read_vec2(), Err(Error::Native(NativeError::AccessorDimensions("read_vec2")))));
+    }
+
+    #[test]
+    fn strided_and_sparse() {
+        //normalized u8 vec2's interleaved with padding, then the second one replaced sparsely
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 16, "uri": "test.bin" }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 12, "byteStride": 4 },
+                { "buffer": 0, "byteOffset": 12, "byteLength": 1 },
+                { "buffer": 0, "byteOffset": 14, "byteLength": 2 }
+            ],
+            "accessors": [{ "bufferView": 0, "componentType": 5121, "normalized": true, "count": 3, "type": "VEC2", "sparse": {
+                "count": 1,
+                "indices": { "bufferView": 1, "componentType": 5121 },
+                "values": { "bufferView": 2 }
+            }}]
+        }"#;
+        let resource = GltfResource {
+            gltf: gltf::Gltf::from_slice(json.as_bytes()).unwrap().document,
+            buffers: vec![vec![0, 255, 9, 9, 51, 51, 9, 9, 255, 0, 9, 9, 1, 0, 102, 102]],
+            images: Vec::<()>::new(),
+        };
+        let reader = resource.read_accessor(&get_accessor(&resource, 0)).unwrap();
+
+        assert_eq!(reader.count(), 3);
+        assert_eq!(reader.bytes(), &[0, 255, 102, 102, 255, 0]);
+        assert_eq!(reader.read_vec2().unwrap(), vec![[0.0, 1.0], [0.4, 0.4], [1.0, 0.0]]);
+    }
+}
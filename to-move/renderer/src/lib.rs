@@ -1,9 +1,8 @@
-/*
-#![feature(option_result_contains)]
+//every module is a folder with a same-named file re-exported by its mod.rs
+#![allow(clippy::module_inception)]
 
 mod renderer;
 
-pub(crate) mod shaders;
 pub(crate) mod primitives;
 /// re-exported
 pub use awsm_web::*;
@@ -15,5 +14,8 @@ pub mod components;
 pub mod gltf;
 pub mod errors;
 pub mod nodes;
+pub mod shaders;
+pub mod materials;
+pub mod textures;
+pub mod lights;
 pub use self::renderer::*;
-*/
\ No newline at end of file
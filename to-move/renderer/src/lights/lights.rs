@@ -0,0 +1,279 @@
+use crate::errors::Error;
+use crate::renderer::Renderer;
+use crate::transform::{WorldTransform, TransformValues};
+use awsm_web::webgl::{BufferData, BufferTarget, BufferUsage};
+use shipyard::prelude::*;
+
+/// Must match MAX_LIGHTS in glsl/chunks/lights.glsl
+/// Lights past this are ignored
+pub const MAX_LIGHTS:usize = 8;
+
+/// Component on nodes, following KHR_lights_punctual
+/// Lights shine down the node's -Z axis
+/// Intensity is in lux for directional lights, candela for the others
+/// A range of None means the light is only limited by inverse-square falloff
+///
+/// A scene without any lights isn't left black: it's lit by a white directional light
+/// (see DEFAULT_LIGHT_DIRECTION and DEFAULT_LIGHT_INTENSITY), which goes away as soon as there's a Light
+#[derive(Clone, Debug, PartialEq)]
+pub enum Light {
+    Directional {
+        color: [f32;3],
+        intensity: f32,
+    },
+    Point {
+        color: [f32;3],
+        intensity: f32,
+        range: Option<f32>,
+    },
+    Spot {
+        color: [f32;3],
+        intensity: f32,
+        range: Option<f32>,
+        /// radians, from the center to where the falloff starts
+        inner_cone_angle: f32,
+        /// radians, from the center to where the light ends
+        outer_cone_angle: f32,
+    },
+}
+
+/// The direction of the light that's used when there are no lights in the scene
+/// (in world space, from above and in front when looking down -Z)
+pub const DEFAULT_LIGHT_DIRECTION:[f32;3] = [-0.5, -1.0, -0.75];
+/// The intensity (in lux) of the light that's used when there are no lights in the scene
+pub const DEFAULT_LIGHT_INTENSITY:f32 = 3.0;
+
+//std140 layout of the lights block:
+//vec4 u_light_count, then per light 4 vec4s:
+//position + range, direction + type, color + intensity, spot scale + offset
+const LIGHT_FLOATS:usize = 16;
+
+impl Renderer {
+    pub(crate) fn update_lights_ubo(&mut self) -> Result<(), Error> {
+        let world = self.world.borrow_mut();
+        let webgl = self.webgl.borrow_mut();
+
+        let data = world.run::<(&Light, &WorldTransform), _, _>(|(lights, world_matrices)| {
+            pack_lights(
+                (&lights, &world_matrices)
+                    .iter()
+                    .into_iter()
+                    .map(|(light, world_matrix)| (light, world_matrix.0.to_vec_f32()))
+            )
+        });
+
+        webgl.upload_buffer(
+            self.lights_buffer_id,
+            BufferData::new(
+                &data,
+                BufferTarget::UniformBuffer,
+                BufferUsage::DynamicDraw,
+            ),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// The whole lights block, with each light's world matrix
+/// Lights past MAX_LIGHTS are dropped, and with no lights the default one is written instead
+fn pack_lights<'a>(lights:impl Iterator<Item = (&'a Light, Vec<f32>)>) -> Vec<f32> {
+    let mut data:Vec<f32> = vec![0.0;4 + (MAX_LIGHTS * LIGHT_FLOATS)];
+    let mut count = 0;
+
+    for (light, matrix) in lights.take(MAX_LIGHTS) {
+        let position = [matrix[12], matrix[13], matrix[14]];
+        let direction = normalize([-matrix[8], -matrix[9], -matrix[10]]);
+
+        write_light(&mut data[4 + (count * LIGHT_FLOATS)..], light, position, direction);
+        count += 1;
+    }
+
+    if count == 0 {
+        let light = Light::Directional { color: [1.0, 1.0, 1.0], intensity: DEFAULT_LIGHT_INTENSITY };
+        write_light(&mut data[4..], &light, [0.0, 0.0, 0.0], normalize(DEFAULT_LIGHT_DIRECTION));
+        count = 1;
+    }
+
+    data[0] = count as f32;
+
+    data
+}
+
+fn write_light(data:&mut [f32], light:&Light, position:[f32;3], direction:[f32;3]) {
+    //light type matches LIGHT_* in the shader
+    let (light_type, color, intensity, range, cone) = match light {
+        Light::Directional { color, intensity } => (0.0, color, intensity, None, None),
+        Light::Point { color, intensity, range } => (1.0, color, intensity, *range, None),
+        Light::Spot { color, intensity, range, inner_cone_angle, outer_cone_angle } => (2.0, color, intensity, *range, Some((*inner_cone_angle, *outer_cone_angle))),
+    };
+
+    //see https://github.com/KhronosGroup/glTF/tree/master/extensions/2.0/Khronos/KHR_lights_punctual#inner-and-outer-cone-angles
+    let (spot_scale, spot_offset) = match cone {
+        Some((inner, outer)) => {
+            let scale = 1.0 / (inner.cos() - outer.cos()).max(0.001);
+            (scale, -outer.cos() * scale)
+        },
+        None => (0.0, 1.0)
+    };
+
+    data[0..16].copy_from_slice(&[
+        position[0], position[1], position[2], range.unwrap_or(0.0),
+        direction[0], direction[1], direction[2], light_type,
+        color[0], color[1], color[2], *intensity,
+        spot_scale, spot_offset, 0.0, 0.0,
+    ]);
+}
+
+fn normalize(v:[f32;3]) -> [f32;3] {
+    let len = ((v[0] * v[0]) + (v[1] * v[1]) + (v[2] * v[2])).sqrt();
+    if len == 0.0 {
+        v
+    } else {
+        [v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_4;
+
+    //identity rotation (pointing down -Z), at the given position
+    fn get_matrix(position:[f32;3]) -> Vec<f32> {
+        vec![
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            position[0], position[1], position[2], 1.0,
+        ]
+    }
+
+    fn get_point(intensity:f32) -> Light {
+        Light::Point { color: [1.0, 0.5, 0.25], intensity, range: Some(10.0) }
+    }
+
+    //the floats of the nth light
+    fn get_light(data:&[f32], index:usize) -> &[f32] {
+        &data[4 + (index * LIGHT_FLOATS)..4 + ((index + 1) * LIGHT_FLOATS)]
+    }
+
+    fn assert_close(actual:f32, expected:f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn std140_layout() {
+        let lights = [get_point(2.0), Light::Directional { color: [0.1, 0.2, 0.3], intensity: 4.0 }];
+        let data = pack_lights(lights.iter().zip(vec![get_matrix([1.0, 2.0, 3.0]), get_matrix([0.0, 0.0, 0.0])]));
+
+        //vec4 u_light_count, then MAX_LIGHTS structs of 4 vec4s
+        assert_eq!(data.len() * 4, 16 + (MAX_LIGHTS * 64));
+        //count, then padding
+        assert_eq!(&data[0..4], &[2.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(get_light(&data, 0), &[
+            1.0, 2.0, 3.0, 10.0,
+            0.0, 0.0, -1.0, 1.0,
+            1.0, 0.5, 0.25, 2.0,
+            0.0, 1.0, 0.0, 0.0,
+        ]);
+
+        //starts at byte 16 + 64
+        assert_eq!(&data[20..24], &[0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(&data[24..28], &[0.0, 0.0, -1.0, 0.0]);
+        assert_eq!(&data[28..32], &[0.1, 0.2, 0.3, 4.0]);
+
+        //the rest are zeroed
+        assert!(data[4 + (2 * LIGHT_FLOATS)..].iter().all(|value| *value == 0.0));
+    }
+
+    #[test]
+    fn direction_follows_rotation() {
+        //rotated 90 degrees around y, so -Z becomes -X
+        let matrix = vec![
+            0.0, 0.0, -1.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        let light = get_point(1.0);
+        let data = pack_lights(vec![(&light, matrix)].into_iter());
+
+        assert_eq!(&get_light(&data, 0)[4..8], &[-1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn truncated_to_max_lights() {
+        let lights:Vec<Light> = (0..MAX_LIGHTS + 3).map(|index| get_point(index as f32)).collect();
+        let data = pack_lights(lights.iter().map(|light| (light, get_matrix([0.0, 0.0, 0.0]))));
+
+        assert_eq!(data[0], MAX_LIGHTS as f32);
+        assert_eq!(data.len(), 4 + (MAX_LIGHTS * LIGHT_FLOATS));
+        //the first ones are kept
+        assert_eq!(get_light(&data, MAX_LIGHTS - 1)[11], (MAX_LIGHTS - 1) as f32);
+    }
+
+    #[test]
+    fn unlimited_range() {
+        let light = Light::Point { color: [1.0, 1.0, 1.0], intensity: 1.0, range: None };
+        let data = pack_lights(vec![(&light, get_matrix([0.0, 0.0, 0.0]))].into_iter());
+
+        //0 is unlimited in the shader
+        assert_eq!(get_light(&data, 0)[3], 0.0);
+    }
+
+    #[test]
+    fn spot_cone() {
+        let light = Light::Spot {
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: None,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: FRAC_PI_4,
+        };
+        let data = pack_lights(vec![(&light, get_matrix([0.0, 0.0, 0.0]))].into_iter());
+        let spot = &get_light(&data, 0)[12..16];
+
+        assert_eq!(get_light(&data, 0)[7], 2.0);
+
+        //scale = 1 / (cos(inner) - cos(outer)), offset = -cos(outer) * scale
+        let scale = 1.0 / (1.0 - FRAC_PI_4.cos());
+        assert_close(spot[0], scale);
+        assert_close(spot[1], -FRAC_PI_4.cos() * scale);
+
+        //so the attenuation (before squaring) is 1 at the inner angle and 0 at the outer one
+        assert_close(spot[0] + spot[1], 1.0);
+        assert_close((FRAC_PI_4.cos() * spot[0]) + spot[1], 0.0);
+        assert_eq!(&spot[2..4], &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn spot_cone_without_falloff() {
+        let light = Light::Spot {
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: None,
+            inner_cone_angle: FRAC_PI_4,
+            outer_cone_angle: FRAC_PI_4,
+        };
+        let data = pack_lights(vec![(&light, get_matrix([0.0, 0.0, 0.0]))].into_iter());
+
+        //clamped instead of dividing by zero
+        assert_close(get_light(&data, 0)[12] / 1000.0, 1.0);
+    }
+
+    #[test]
+    fn default_light_without_lights() {
+        let data = pack_lights(Vec::new().into_iter());
+        let direction = normalize(DEFAULT_LIGHT_DIRECTION);
+
+        assert_eq!(data[0], 1.0);
+        assert_eq!(get_light(&data, 0), &[
+            0.0, 0.0, 0.0, 0.0,
+            direction[0], direction[1], direction[2], 0.0,
+            1.0, 1.0, 1.0, DEFAULT_LIGHT_INTENSITY,
+            0.0, 1.0, 0.0, 0.0,
+        ]);
+    }
+}
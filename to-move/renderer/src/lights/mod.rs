@@ -0,0 +1,3 @@
+mod lights;
+
+pub use self::lights::*;
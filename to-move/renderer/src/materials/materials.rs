@@ -0,0 +1,248 @@
+use awsm_web::webgl::{WebGl2Renderer, Id};
+use crate::errors::{Error, NativeError, allow_missing_uniform};
+use crate::renderer::Renderer;
+use crate::primitives::Primitive;
+use crate::shaders::{preprocess, SourceMap};
+use shipyard::prelude::*;
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+/// Returned from Renderer::register_material()
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub(crate) usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MaterialParam {
+    Float(f32),
+    Vec2([f32;2]),
+    Vec3([f32;3]),
+    Vec4([f32;4]),
+    Int(i32),
+    Mat4([f32;16]),
+    /// A texture id from webgl, bound to the sampler of the same name
+    Texture(Id),
+}
+
+impl MaterialParam {
+    pub fn same_type(&self, other:&MaterialParam) -> bool {
+        discriminant(self) == discriminant(other)
+    }
+}
+
+pub type MaterialParams = HashMap<String, MaterialParam>;
+
+/// A registered shader pair and the params it takes (with their defaults)
+pub struct CustomMaterialDef {
+    pub program_id: Id,
+    pub params: MaterialParams,
+}
+
+/// Component on primitives that are drawn with a custom material instead of the built-in shader
+/// params only holds the overrides, anything else uses the registered default
+#[derive(Clone, Debug)]
+pub struct CustomMaterial {
+    pub id: MaterialId,
+    pub params: MaterialParams,
+}
+
+impl Renderer {
+    /// The shaders go through the same preprocessor as the built-in ones
+    /// so they can #include "camera" for the camera uniform block
+    /// and are expected to declare `uniform mat4 u_model;`
+    ///
+    /// Primitives keep the vertex arrays they were uploaded with, which bind each attribute
+    /// to a fixed location, so the vertex shader has to declare the ones it reads at that location
+    /// e.g. `layout(location = 0) in vec3 a_position;` (see shaders::ATTRIBUTE_LOCATIONS)
+    ///
+    /// Every param must be given here with its default value, and that decides its type
+    pub fn register_material(&mut self, vertex:&str, fragment:&str, params:MaterialParams) -> Result<MaterialId, Error> {
+        let mut webgl = self.webgl.borrow_mut();
+        let chunks = self.shader_cache.chunks();
+
+        let mut source_map = SourceMap::new();
+        let vertex = preprocess("vertex", vertex, &[], chunks, &mut source_map)?;
+        let fragment = preprocess("fragment", fragment, &[], chunks, &mut source_map)?;
+
+        let program_id = webgl
+            .compile_program(&vertex, &fragment)
+            .map_err(|err| NativeError::ShaderCompile(source_map.map_error_log(&Error::from(err).to_string())))?;
+
+        self.custom_materials.push(CustomMaterialDef { program_id, params });
+
+        Ok(MaterialId(self.custom_materials.len() - 1))
+    }
+
+    /// Assigns the material to the primitives at or under this node
+    /// Any previous overrides on those primitives are dropped
+    pub fn set_material(&mut self, node:EntityId, material:MaterialId) -> Result<(), Error> {
+        if material.0 >= self.custom_materials.len() {
+            return Err(NativeError::MaterialMissing.into());
+        }
+
+        let keys = self.get_primitive_keys(node);
+        let world = self.world.borrow_mut();
+
+        world.run::<(EntitiesMut, &mut CustomMaterial), _, _>(|(entities, mut custom_materials)| {
+            for key in keys {
+                let custom_material = CustomMaterial { id: material, params: MaterialParams::new() };
+                match (&mut custom_materials).get(key).ok() {
+                    Some(existing) => *existing = custom_material,
+                    None => entities.add_component(&mut custom_materials, custom_material, key)
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Overrides the param for the primitives at or under this node
+    /// It must be declared in the material with the same type
+    /// If it isn't, for any of the primitives, none of them are changed
+    pub fn set_material_param(&mut self, node:EntityId, name:&str, param:MaterialParam) -> Result<(), Error> {
+        let keys = self.get_primitive_keys(node);
+        set_material_param(&self.world.borrow(), &self.custom_materials, &keys, name, param)
+    }
+
+    /// Goes back to the built-in shader for the primitives at or under this node
+    pub fn clear_material(&mut self, node:EntityId) {
+        let keys = self.get_primitive_keys(node);
+        let world = self.world.borrow_mut();
+
+        world.run::<&mut CustomMaterial, _, _>(|mut custom_materials| {
+            for key in keys {
+                Remove::<(CustomMaterial,)>::remove((&mut custom_materials,), key);
+            }
+        });
+    }
+
+    fn get_primitive_keys(&self, node:EntityId) -> Vec<EntityId> {
+        let mut keys = self.get_descendants(node);
+        keys.push(node);
+
+        let world = self.world.borrow();
+        world.run::<&Primitive, _, _>(|primitives| {
+            keys
+                .into_iter()
+                .filter(|key| (&primitives).get(*key).is_ok())
+                .collect()
+        })
+    }
+}
+
+fn set_material_param(world:&World, defs:&[CustomMaterialDef], keys:&[EntityId], name:&str, param:MaterialParam) -> Result<(), Error> {
+    world.run::<&mut CustomMaterial, _, _>(|mut custom_materials| {
+        for key in keys.iter() {
+            if let Ok(custom_material) = (&custom_materials).get(*key) {
+                let declared = defs
+                    .get(custom_material.id.0)
+                    .and_then(|def| def.params.get(name))
+                    .ok_or_else(|| NativeError::MaterialParam(name.to_string()))?;
+
+                if !declared.same_type(&param) {
+                    return Err(NativeError::MaterialParam(name.to_string()).into());
+                }
+            }
+        }
+
+        for key in keys.iter() {
+            if let Ok(custom_material) = (&mut custom_materials).get(*key) {
+                custom_material.params.insert(name.to_string(), param.clone());
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Uploads the declared params, using the instance's value where it's been overridden
+/// Params that the compiler optimized away are skipped, any other failure is returned
+pub(crate) fn upload_material_params(webgl:&mut WebGl2Renderer, def:&CustomMaterialDef, custom_material:&CustomMaterial) -> Result<(), Error> {
+    for (name, default) in def.params.iter() {
+        let param = custom_material.params.get(name).unwrap_or(default);
+
+        allow_missing_uniform(match param {
+            MaterialParam::Float(value) => webgl.upload_uniform_fvec_1(name, &[*value]),
+            MaterialParam::Vec2(value) => webgl.upload_uniform_fvec_2(name, value),
+            MaterialParam::Vec3(value) => webgl.upload_uniform_fvec_3(name, value),
+            MaterialParam::Vec4(value) => webgl.upload_uniform_fvec_4(name, value),
+            MaterialParam::Int(value) => webgl.upload_uniform_ivec_1(name, &[*value]),
+            MaterialParam::Mat4(value) => webgl.upload_uniform_mat_4(name, value),
+            MaterialParam::Texture(texture_id) => webgl.activate_texture_for_sampler(*texture_id, name),
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_def(params:&[(&str, MaterialParam)]) -> CustomMaterialDef {
+        CustomMaterialDef {
+            program_id: beach_map::BeachMap::<beach_map::DefaultVersion, ()>::default().insert(()),
+            params: params.iter().map(|(name, param)| (name.to_string(), param.clone())).collect(),
+        }
+    }
+
+    fn add_primitive(world:&World, material:MaterialId) -> EntityId {
+        world.run::<(EntitiesMut, &mut CustomMaterial), _, _>(|(mut entities, mut custom_materials)| {
+            entities.add_entity((&mut custom_materials,), (CustomMaterial { id: material, params: MaterialParams::new() },))
+        })
+    }
+
+    fn get_params(world:&World, key:EntityId) -> MaterialParams {
+        world.run::<&CustomMaterial, _, _>(|custom_materials| (&custom_materials).get(key).unwrap().params.clone())
+    }
+
+    fn is_param_error(result:Result<(), Error>) -> bool {
+        matches!(result, Err(Error::Native(NativeError::MaterialParam(_))))
+    }
+
+    #[test]
+    fn set_declared_param() {
+        let world = World::default();
+        let defs = vec![get_def(&[("u_color", MaterialParam::Vec4([1.0;4]))])];
+        let key = add_primitive(&world, MaterialId(0));
+
+        set_material_param(&world, &defs, &[key], "u_color", MaterialParam::Vec4([0.5;4])).unwrap();
+        assert_eq!(get_params(&world, key).get("u_color"), Some(&MaterialParam::Vec4([0.5;4])));
+    }
+
+    #[test]
+    fn unknown_param() {
+        let world = World::default();
+        let defs = vec![get_def(&[("u_color", MaterialParam::Vec4([1.0;4]))])];
+        let key = add_primitive(&world, MaterialId(0));
+
+        assert!(is_param_error(set_material_param(&world, &defs, &[key], "u_other", MaterialParam::Vec4([0.5;4]))));
+        assert!(get_params(&world, key).is_empty());
+    }
+
+    #[test]
+    fn param_type_mismatch() {
+        let world = World::default();
+        let defs = vec![get_def(&[("u_color", MaterialParam::Vec4([1.0;4]))])];
+        let key = add_primitive(&world, MaterialId(0));
+
+        assert!(is_param_error(set_material_param(&world, &defs, &[key], "u_color", MaterialParam::Float(0.5))));
+        assert!(get_params(&world, key).is_empty());
+    }
+
+    #[test]
+    fn failed_param_changes_nothing() {
+        let world = World::default();
+        let defs = vec![
+            get_def(&[("u_color", MaterialParam::Vec4([1.0;4]))]),
+            get_def(&[("u_roughness", MaterialParam::Float(1.0))]),
+        ];
+        let declared = add_primitive(&world, MaterialId(0));
+        let undeclared = add_primitive(&world, MaterialId(1));
+
+        //valid for the first, but not the second
+        let result = set_material_param(&world, &defs, &[declared, undeclared], "u_color", MaterialParam::Vec4([0.5;4]));
+        assert!(is_param_error(result));
+        assert!(get_params(&world, declared).is_empty());
+        assert!(get_params(&world, undeclared).is_empty());
+    }
+}
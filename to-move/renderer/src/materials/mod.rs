@@ -0,0 +1,5 @@
+mod materials;
+mod pbr;
+
+pub use self::materials::*;
+pub use self::pbr::*;
@@ -0,0 +1,125 @@
+use awsm_web::webgl::{WebGl2Renderer, Id, GlToggle, BlendFactor};
+use crate::errors::{Error, allow_missing_uniform};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+/// A texture as used by a material
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialTexture {
+    pub texture_id: Id,
+    /// which TEXCOORD set to sample with
+    pub texcoord: u32,
+}
+
+/// Component on primitives, drawn with the built-in metallic-roughness shader
+/// Factors are multiplied with their texture, if there is one
+///
+/// The primitive holds one use (see TextureManager) of the texture in each slot
+/// and releases them when it's removed, so a texture Id that's put on more than one
+/// primitive (or slot) needs a Renderer::retain_texture() for each extra one
+#[derive(Clone, Debug)]
+pub struct PbrMaterial {
+    pub base_color_factor: [f32;4],
+    pub base_color_texture: Option<MaterialTexture>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<MaterialTexture>,
+    pub normal_texture: Option<MaterialTexture>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<MaterialTexture>,
+    pub occlusion_strength: f32,
+    pub emissive_texture: Option<MaterialTexture>,
+    pub emissive_factor: [f32;3],
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl PbrMaterial {
+    /// The texture in each slot that has one, so the same Id can be listed more than once
+    pub fn texture_ids(&self) -> Vec<Id> {
+        [
+            &self.base_color_texture,
+            &self.metallic_roughness_texture,
+            &self.normal_texture,
+            &self.occlusion_texture,
+            &self.emissive_texture,
+        ]
+            .iter()
+            .filter_map(|texture| texture.as_ref())
+            .map(|texture| texture.texture_id)
+            .collect()
+    }
+}
+
+/// Same as the glTF defaults
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_texture: None,
+            emissive_factor: [0.0, 0.0, 0.0],
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+/// Sets the uniforms, textures and render state for the material
+/// Expects the program to already be active
+pub(crate) fn upload_pbr_material(webgl:&mut WebGl2Renderer, material:&PbrMaterial) -> Result<(), Error> {
+    webgl.upload_uniform_fvec_4("u_base_color_factor", &material.base_color_factor)?;
+    webgl.upload_uniform_fvec_1("u_metallic_factor", &[material.metallic_factor])?;
+    webgl.upload_uniform_fvec_1("u_roughness_factor", &[material.roughness_factor])?;
+    webgl.upload_uniform_fvec_3("u_emissive_factor", &material.emissive_factor)?;
+
+    let textures = [
+        (&material.base_color_texture, "u_base_color_sampler", "u_base_color_texcoord"),
+        (&material.metallic_roughness_texture, "u_metallic_roughness_sampler", "u_metallic_roughness_texcoord"),
+        (&material.normal_texture, "u_normal_sampler", "u_normal_texcoord"),
+        (&material.occlusion_texture, "u_occlusion_sampler", "u_occlusion_texcoord"),
+        (&material.emissive_texture, "u_emissive_sampler", "u_emissive_texcoord"),
+    ];
+
+    for (texture, sampler_name, texcoord_name) in textures.iter() {
+        if let Some(texture) = texture {
+            webgl.activate_texture_for_sampler(texture.texture_id, sampler_name)?;
+            //only read when there's a second set of texcoords
+            allow_missing_uniform(webgl.upload_uniform_ivec_1(texcoord_name, &[texture.texcoord as i32]))?;
+        }
+    }
+
+    //only used by some permutations, so the compiler may have stripped them
+    allow_missing_uniform(webgl.upload_uniform_fvec_1("u_normal_scale", &[material.normal_scale]))?;
+    allow_missing_uniform(webgl.upload_uniform_fvec_1("u_occlusion_strength", &[material.occlusion_strength]))?;
+    allow_missing_uniform(webgl.upload_uniform_fvec_1("u_alpha_cutoff", &[material.alpha_cutoff]))?;
+
+    webgl.toggle(GlToggle::CullFace, !material.double_sided);
+
+    //TODO - blended primitives should be drawn last, sorted back to front
+    match material.alpha_mode {
+        AlphaMode::Blend => {
+            webgl.toggle(GlToggle::Blend, true);
+            webgl.set_blend_func(BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha);
+        },
+        _ => {
+            webgl.toggle(GlToggle::Blend, false);
+        }
+    }
+
+    Ok(())
+}
@@ -1,2 +1,3 @@
 mod nodes;
+mod traversal;
 pub use self::nodes::*;
\ No newline at end of file
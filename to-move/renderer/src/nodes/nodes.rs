@@ -1,39 +1,61 @@
-use crate::errors::Error;
+use crate::errors::{Error, NativeError};
 use crate::renderer::Renderer;
 use crate::transform::*;
 use crate::components::*;
 use shipyard::prelude::*;
+use awsm_web::webgl::Id;
+use std::collections::HashSet;
 
 pub struct Node {
-    //just an idea so far to try and keep flat list... might not pan out
-    pub n_children: usize,
+    pub(crate) parent: Option<EntityId>,
+    pub(crate) children: Vec<EntityId>,
+    //the trs changed, so the local matrix (and the world matrices of the subtree) need updating
+    pub(crate) dirty: bool,
 }
 impl Node {
-    pub fn new() -> Self {
+    pub fn new(parent:Option<EntityId>) -> Self {
         Self{
-            n_children: 0
+            parent,
+            children: Vec::new(),
+            dirty: true,
         }
     }
+
+    pub fn parent(&self) -> Option<EntityId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[EntityId] {
+        &self.children
+    }
 }
 
+/// Imported from the gltf node name (or mesh name, for primitives)
+pub struct Name(pub String);
+
+/// Free-form tags for looking nodes up from gameplay code
+pub struct Tags(pub HashSet<String>);
+
 pub enum NodeData {
     Empty,
     Camera(Matrix4), //Projection matrix. View Matrix is calculated from trs
-    Primitive(Primitive)
+    Primitive(Primitive),
+    Light(Light),
 }
 
 impl Renderer {
     /// Adds a node to the scene
-    pub fn add_node(&mut self, data:NodeData, parent:Option<Key>, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) -> Result<Key, Error> {
+    pub fn add_node(&mut self, data:NodeData, parent:Option<EntityId>, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) -> Result<EntityId, Error> {
         add_node(&mut self.world.borrow_mut(), data, parent, translation, rotation, scale)
     }
 
-    pub fn set_node_trs(&mut self, node:Key, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) {
+    pub fn set_node_trs(&mut self, node:EntityId, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) {
         let world = self.world.borrow_mut();
 
-        world.run::<(&mut Translation, &mut Rotation, &mut Scale), _, _>(
-            |(mut translations, mut rotations, mut scales)| {
-                if let Some((t,r,s)) = (&mut translations, &mut rotations, &mut scales).get(node).iter_mut().next() {
+        world.run::<(&mut Node, &mut Translation, &mut Rotation, &mut Scale), _, _>(
+            |(mut nodes, mut translations, mut rotations, mut scales)| {
+                if let Ok((n,t,r,s)) = (&mut nodes, &mut translations, &mut rotations, &mut scales).get(node) {
+                    n.dirty = true;
                     if let Some(translation) = translation {
                         t.0.copy_from(&translation);
                     }
@@ -47,10 +69,42 @@ impl Renderer {
             }
         );
     }
+
+    /// Removes the node and all its descendants
+    /// GPU data of removed primitives is released if nothing else uses it
+    pub fn remove_node(&mut self, node:EntityId) -> Result<(), Error> {
+        self.remove_subtree(&[node])
+    }
+
+    /// Removes several subtrees at once (e.g. the roots returned from upload_gltf)
+    pub fn remove_subtree(&mut self, roots:&[EntityId]) -> Result<(), Error> {
+        let removed = remove_subtree(&self.world.borrow(), roots);
+
+        self.release_textures(removed.texture_ids)?;
+        self.release_vertex_arrays(removed.vao_ids)
+    }
+
+    /// Moves the node under a new parent (or makes it a root if None)
+    /// The local transform is kept, so the world transform follows the new parent
+    pub fn set_parent(&mut self, node:EntityId, parent:Option<EntityId>) -> Result<(), Error> {
+        set_parent(&self.world.borrow(), node, parent)
+    }
+
+    /// Only needed if Translation, Rotation, or Scale was changed directly in the world
+    /// (set_node_trs takes care of it otherwise)
+    pub fn mark_transform_dirty(&mut self, node:EntityId) {
+        let world = self.world.borrow_mut();
+
+        world.run::<&mut Node, _, _>(|mut nodes| {
+            if let Ok(node) = (&mut nodes).get(node) {
+                node.dirty = true;
+            }
+        });
+    }
 }
 
 //Mostly for internal use - but can also be used to share the ECS outside of renderer
-pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) -> Result<Key, Error> {
+pub fn add_node(world:&mut World, data:NodeData, parent:Option<EntityId>, translation: Option<Vector3>, rotation: Option<Quaternion>, scale: Option<Vector3>) -> Result<EntityId, Error> {
     let translation = translation.unwrap_or_default();
     let rotation = rotation.unwrap_or_default();
     let scale = scale.unwrap_or(Vector3::new(1.0, 1.0, 1.0));
@@ -58,8 +112,10 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
     let world_matrix = Matrix4::default();
 
     if let Some(parent) = parent {
-        //TODO - re-arrange all the nodes?
-        //probably do *not* need to mess with world matrix, just mark dirty and it'll be updated...
+        let parent_exists = world.run::<&Node, _, _>(|nodes| (&nodes).get(parent).is_ok());
+        if !parent_exists {
+            return Err(NativeError::ParentMissing.into());
+        }
     }
 
     let node = match data {
@@ -91,7 +147,7 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
                         &mut world_matrices,
                     ), 
                     (
-                        Node::new(),
+                        Node::new(parent),
                         Translation(translation),
                         Rotation(rotation),
                         Scale(scale),
@@ -137,7 +193,7 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
                         &mut world_matrices,
                     ), 
                     (
-                        Node::new(),
+                        Node::new(parent),
                         CameraView(camera_view),
                         CameraProjection(projection_matrix),
                         Translation(translation),
@@ -150,6 +206,49 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
             })
         }
 
+        NodeData::Light(light) => {
+            world.run::<(
+                EntitiesMut, 
+                &mut Node,
+                &mut Light,
+                &mut Translation,
+                &mut Rotation,
+                &mut Scale,
+                &mut LocalTransform,
+                &mut WorldTransform,
+            ), _, _>(|(
+                mut entities, 
+                mut nodes,
+                mut lights,
+                mut translations,
+                mut rotations,
+                mut scales,
+                mut local_matrices,
+                mut world_matrices,
+            )| {
+                Ok(entities.add_entity(
+                    (
+                        &mut nodes,
+                        &mut lights, 
+                        &mut translations,
+                        &mut rotations,
+                        &mut scales,
+                        &mut local_matrices,
+                        &mut world_matrices,
+                    ), 
+                    (
+                        Node::new(parent),
+                        light,
+                        Translation(translation),
+                        Rotation(rotation),
+                        Scale(scale),
+                        LocalTransform(local_matrix),
+                        WorldTransform(world_matrix),
+                    )
+                ))
+            })
+        }
+
         NodeData::Primitive(primitive) => {
             world.run::<(
                 EntitiesMut, 
@@ -181,7 +280,7 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
                         &mut world_matrices,
                     ), 
                     (
-                        Node::new(),
+                        Node::new(parent),
                         primitive,
                         Translation(translation),
                         Rotation(rotation),
@@ -194,5 +293,504 @@ pub fn add_node(world:&mut World, data:NodeData, parent:Option<Key>, translation
         }
     };
 
+    //the world matrix will be updated from the parent on the next update_transforms()
+    if let (Ok(node), Some(parent)) = (&node, parent) {
+        world.run::<&mut Node, _, _>(|mut nodes| {
+            if let Ok(parent) = (&mut nodes).get(parent) {
+                parent.children.push(*node);
+            }
+        });
+    }
+
     node
-}
\ No newline at end of file
+}
+
+/// The GPU data that was used by the removed primitives
+pub(crate) struct RemovedNodes {
+    pub vao_ids: Vec<Id>,
+    //one entry per material slot, see PbrMaterial
+    pub texture_ids: Vec<Id>,
+}
+
+/// Removes the roots and everything below them from the world
+/// The roots are taken out of their parents' children
+pub(crate) fn remove_subtree(world:&World, roots:&[EntityId]) -> RemovedNodes {
+    let keys = world.run::<&mut Node, _, _>(|mut nodes| {
+        for root in roots {
+            let parent = (&nodes).get(*root).ok().and_then(|node| node.parent);
+            if let Some(parent) = parent.and_then(|parent| (&mut nodes).get(parent).ok()) {
+                parent.children.retain(|child| child != root);
+            }
+        }
+
+        get_subtrees(&nodes, roots)
+    });
+
+    let removed = world.run::<(&Primitive, &PbrMaterial), _, _>(|(primitives, materials)| {
+        RemovedNodes {
+            vao_ids: keys.iter()
+                .filter_map(|key| (&primitives).get(*key).ok())
+                .map(|primitive| primitive.vao_id)
+                .collect(),
+            texture_ids: keys.iter()
+                .filter_map(|key| (&materials).get(*key).ok())
+                .flat_map(|material| material.texture_ids())
+                .collect(),
+        }
+    });
+
+    world.run::<AllStorages, _, _>(|mut all_storages| {
+        for key in keys {
+            all_storages.delete(key);
+        }
+    });
+
+    removed
+}
+
+/// Moving a node under itself or one of its descendants is an error
+pub fn set_parent(world:&World, node:EntityId, parent:Option<EntityId>) -> Result<(), Error> {
+    world.run::<&mut Node, _, _>(|mut nodes| {
+        let old_parent = (&nodes).get(node).ok().ok_or(NativeError::NodeKeyMissing)?.parent;
+
+        if let Some(parent) = parent {
+            //walk up from the new parent to make sure we're not moving under ourselves
+            let mut ancestor = Some(parent);
+            while let Some(key) = ancestor {
+                if key == node {
+                    return Err(NativeError::ParentCycle.into());
+                }
+                ancestor = (&nodes).get(key).ok().ok_or(NativeError::ParentMissing)?.parent;
+            }
+        }
+
+        if let Some(old_parent) = old_parent.and_then(|old_parent| (&mut nodes).get(old_parent).ok()) {
+            old_parent.children.retain(|child| *child != node);
+        }
+        if let Some(parent) = parent.and_then(|parent| (&mut nodes).get(parent).ok()) {
+            parent.children.push(node);
+        }
+        if let Ok(node) = (&mut nodes).get(node) {
+            node.parent = parent;
+            node.dirty = true;
+        }
+
+        Ok(())
+    })
+}
+
+/// The roots and everything below them, each key only once
+/// Depth-first (pre-order), in the order of the roots and children
+/// Works with either a View or a ViewMut of the nodes
+pub(crate) fn get_subtrees<'a, N>(nodes:N, roots:&[EntityId]) -> Vec<EntityId> 
+where N: GetComponent<Out = &'a Node> + Copy
+{
+    let mut keys = Vec::new();
+    let mut visited = HashSet::new();
+    //reversed so that the first one is popped first
+    let mut stack:Vec<EntityId> = roots.iter().rev().copied().collect();
+
+    while let Some(key) = stack.pop() {
+        if visited.insert(key) {
+            keys.push(key);
+            if let Ok(node) = nodes.get(key) {
+                stack.extend(node.children.iter().rev().copied());
+            }
+        }
+    }
+
+    keys
+}
+
+/// Recomputes the local matrices of dirty nodes and the world matrices of their subtrees
+/// Called at the top of each render, but can also be used to share the ECS outside of renderer
+pub fn update_transforms(world:&World) {
+    //Depth-first from the roots, so parents are always updated before their children
+    //Only dirty nodes get their local matrix updated
+    //and only their subtrees get their world matrices updated
+    world.run::<(&mut Node, &Translation, &Rotation, &Scale, &mut LocalTransform, &mut WorldTransform), _, _>(
+        |(mut nodes, translations, rotations, scales, mut local_matrices, mut world_matrices)| {
+        //(node, parent world matrix changed)
+        let mut stack:Vec<(EntityId, bool)> = nodes
+            .iter()
+            .with_id()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| (id, false))
+            .collect();
+
+        while let Some((key, parent_dirty)) = stack.pop() {
+            if let Ok(node) = (&mut nodes).get(key) {
+                let local_dirty = node.dirty;
+                let dirty = local_dirty || parent_dirty;
+                let parent = node.parent;
+                node.dirty = false;
+                stack.extend(node.children.iter().map(|child| (*child, dirty)));
+
+                if local_dirty {
+                    if let Ok((translation, rotation, scale, local_matrix)) = (&translations, &rotations, &scales, &mut local_matrices).get(key) {
+                        local_matrix.0.from_trs_mut(&translation.0, &rotation.0, &scale.0);
+                    }
+                }
+
+                if dirty {
+                    let parent_matrix = parent
+                        .and_then(|parent| (&world_matrices).get(parent).ok())
+                        .map(|parent_matrix| parent_matrix.0.clone());
+
+                    if let (Some(local_matrix), Some(world_matrix)) = ((&local_matrices).get(key).ok(), (&mut world_matrices).get(key).ok()) {
+                        let local_matrix = &local_matrix.0;
+                        let world_matrix = &mut world_matrix.0;
+                        match parent_matrix {
+                            Some(parent_matrix) => {
+                                world_matrix.copy_from(&parent_matrix);
+                                world_matrix.mul_mut(local_matrix);
+                            },
+                            None => {
+                                world_matrix.copy_from(local_matrix);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Replaces the name if there already is one
+pub fn set_node_name(world:&mut World, node:EntityId, name:&str) {
+    world.run::<(EntitiesMut, &mut Name), _, _>(|(entities, mut names)| {
+        match (&mut names).get(node).ok() {
+            Some(existing) => {
+                existing.0 = name.to_string();
+            },
+            None => {
+                entities.add_component(&mut names, Name(name.to_string()), node);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::MaterialTexture;
+    use crate::primitives::PrimitiveDraw;
+    use awsm_web::webgl::BeginMode;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    fn get_world() -> World {
+        let mut world = World::default();
+        register_components(&mut world);
+        world
+    }
+
+    fn add(world:&mut World, parent:Option<EntityId>, x:f64, y:f64, z:f64) -> EntityId {
+        add_node(world, NodeData::Empty, parent, Some(Vector3::new(x, y, z)), None, None).unwrap()
+    }
+
+    fn get_position(world:&World, key:EntityId) -> [f64;3] {
+        world.run::<&WorldTransform, _, _>(|world_matrices| {
+            let matrix:&[f64] = (&world_matrices).get(key).unwrap().0.as_ref();
+            [matrix[12], matrix[13], matrix[14]]
+        })
+    }
+
+    fn assert_position(world:&World, key:EntityId, expected:[f64;3]) {
+        let position = get_position(world, key);
+        for (actual, expected) in position.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-9, "{:?} != {:?}", position, expected);
+        }
+    }
+
+    #[test]
+    fn deep_hierarchy() {
+        let mut world = get_world();
+        let mut keys = vec![add(&mut world, None, 1.0, 0.0, 0.0)];
+        for _ in 1..100 {
+            let parent = *keys.last().unwrap();
+            keys.push(add(&mut world, Some(parent), 1.0, 0.0, 0.0));
+        }
+
+        update_transforms(&world);
+
+        for (index, key) in keys.iter().enumerate() {
+            assert_position(&world, *key, [(index + 1) as f64, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn wide_hierarchy() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 0.0, 1.0, 0.0);
+        let children:Vec<EntityId> = (0..100)
+            .map(|index| add(&mut world, Some(root), index as f64, 0.0, 0.0))
+            .collect();
+
+        update_transforms(&world);
+
+        assert_position(&world, root, [0.0, 1.0, 0.0]);
+        for (index, child) in children.iter().enumerate() {
+            assert_position(&world, *child, [index as f64, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn parent_trs_order() {
+        let mut world = get_world();
+        //rotated 90 degrees around z and scaled by 2, then moved
+        let parent = add_node(
+            &mut world,
+            NodeData::Empty,
+            None,
+            Some(Vector3::new(10.0, 0.0, 0.0)),
+            Some(Quaternion::new(0.0, 0.0, FRAC_1_SQRT_2, FRAC_1_SQRT_2)),
+            Some(Vector3::new(2.0, 2.0, 2.0)),
+        ).unwrap();
+        let child = add(&mut world, Some(parent), 1.0, 0.0, 0.0);
+
+        update_transforms(&world);
+
+        //T * R * S: the child's offset is scaled, then rotated onto +y, then translated
+        assert_position(&world, child, [10.0, 2.0, 0.0]);
+    }
+
+    const STALE:[f64;3] = [100.0, 0.0, 0.0];
+
+    //overwrites every world matrix with a translation to STALE
+    //anything the next update doesn't touch keeps it
+    fn set_stale(world:&World) {
+        world.run::<&mut WorldTransform, _, _>(|mut world_matrices| {
+            for world_matrix in (&mut world_matrices).iter().into_iter() {
+                world_matrix.0.copy_from(&Matrix4::from_translation(&Vector3::new(STALE[0], STALE[1], STALE[2])));
+            }
+        });
+    }
+
+    fn set_translation(world:&World, key:EntityId, x:f64, y:f64, z:f64) {
+        world.run::<(&mut Node, &mut Translation), _, _>(|(mut nodes, mut translations)| {
+            (&mut translations).get(key).unwrap().0.copy_from(&Vector3::new(x, y, z));
+            (&mut nodes).get(key).unwrap().dirty = true;
+        });
+    }
+
+    fn is_dirty(world:&World, key:EntityId) -> bool {
+        world.run::<&Node, _, _>(|nodes| (&nodes).get(key).unwrap().dirty)
+    }
+
+    #[test]
+    fn only_dirty_subtrees_update() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 1.0, 0.0, 0.0);
+        let inner = add(&mut world, Some(root), 0.0, 1.0, 0.0);
+        let inner_a = add(&mut world, Some(inner), 0.0, 0.0, 1.0);
+        let inner_b = add(&mut world, Some(inner), 0.0, 0.0, 2.0);
+        let other = add(&mut world, Some(root), 0.0, 2.0, 0.0);
+        let leaf = add(&mut world, Some(other), 0.0, 0.0, 3.0);
+        let untouched = add(&mut world, Some(root), 0.0, 3.0, 0.0);
+        let all = [root, inner, inner_a, inner_b, other, leaf, untouched];
+
+        update_transforms(&world);
+        assert!(all.iter().all(|key| !is_dirty(&world, *key)));
+
+        set_stale(&world);
+        set_translation(&world, inner, 0.0, 5.0, 0.0);
+        set_translation(&world, leaf, 0.0, 0.0, 7.0);
+
+        update_transforms(&world);
+
+        //clean nodes aren't recomputed
+        assert_position(&world, root, STALE);
+        assert_position(&world, other, STALE);
+        assert_position(&world, untouched, STALE);
+        //dirty nodes and their subtrees are, on top of the (untouched) parent
+        assert_position(&world, inner, [100.0, 5.0, 0.0]);
+        assert_position(&world, inner_a, [100.0, 5.0, 1.0]);
+        assert_position(&world, inner_b, [100.0, 5.0, 2.0]);
+        assert_position(&world, leaf, [100.0, 0.0, 7.0]);
+
+        assert!(all.iter().all(|key| !is_dirty(&world, *key)));
+    }
+
+    #[test]
+    fn clean_update_touches_nothing() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 1.0, 0.0, 0.0);
+        let child = add(&mut world, Some(root), 0.0, 1.0, 0.0);
+
+        update_transforms(&world);
+        set_stale(&world);
+        update_transforms(&world);
+
+        assert_position(&world, root, STALE);
+        assert_position(&world, child, STALE);
+    }
+
+    fn get_ids(count:usize) -> Vec<Id> {
+        let mut ids = beach_map::BeachMap::<beach_map::DefaultVersion, ()>::default();
+        (0..count).map(|_| ids.insert(())).collect()
+    }
+
+    fn add_primitive(world:&mut World, parent:EntityId, vao_id:Id, material:PbrMaterial) -> EntityId {
+        let primitive = Primitive {
+            shader_id: vao_id,
+            vao_id,
+            draw_info: PrimitiveDraw::Direct(BeginMode::Triangles, 3, 0),
+        };
+        let key = add_node(world, NodeData::Primitive(primitive), Some(parent), None, None, None).unwrap();
+        world.run::<(EntitiesMut, &mut PbrMaterial), _, _>(|(entities, mut materials)| {
+            entities.add_component(&mut materials, material, key);
+        });
+        key
+    }
+
+    fn exists(world:&World, key:EntityId) -> bool {
+        world.run::<&Node, _, _>(|nodes| (&nodes).get(key).is_ok())
+    }
+
+    fn get_parent(world:&World, key:EntityId) -> Option<EntityId> {
+        world.run::<&Node, _, _>(|nodes| (&nodes).get(key).unwrap().parent)
+    }
+
+    fn get_children(world:&World, key:EntityId) -> Vec<EntityId> {
+        world.run::<&Node, _, _>(|nodes| (&nodes).get(key).unwrap().children.clone())
+    }
+
+    #[test]
+    fn remove_subtree_removes_descendants() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 0.0, 0.0, 0.0);
+        let branch = add(&mut world, Some(root), 0.0, 0.0, 0.0);
+        let leaf_a = add(&mut world, Some(branch), 0.0, 0.0, 0.0);
+        let leaf_b = add(&mut world, Some(leaf_a), 0.0, 0.0, 0.0);
+        let sibling = add(&mut world, Some(root), 0.0, 0.0, 0.0);
+        let other_root = add(&mut world, None, 0.0, 0.0, 0.0);
+        let other_leaf = add(&mut world, Some(other_root), 0.0, 0.0, 0.0);
+
+        remove_subtree(&world, &[branch, other_root]);
+
+        for key in &[branch, leaf_a, leaf_b, other_root, other_leaf] {
+            assert!(!exists(&world, *key));
+        }
+        assert!(exists(&world, root));
+        assert!(exists(&world, sibling));
+        assert_eq!(get_children(&world, root), vec![sibling]);
+
+        //what's left still updates
+        update_transforms(&world);
+    }
+
+    #[test]
+    fn remove_subtree_returns_gpu_data() {
+        let mut world = get_world();
+        let ids = get_ids(4);
+        let (vao_a, vao_b, texture_a, texture_b) = (ids[0], ids[1], ids[2], ids[3]);
+        let texture = |texture_id| Some(MaterialTexture { texture_id, texcoord: 0 });
+
+        let root = add(&mut world, None, 0.0, 0.0, 0.0);
+        let mesh = add(&mut world, Some(root), 0.0, 0.0, 0.0);
+        add_primitive(&mut world, mesh, vao_a, PbrMaterial {
+            base_color_texture: texture(texture_a),
+            normal_texture: texture(texture_b),
+            ..PbrMaterial::default()
+        });
+        add_primitive(&mut world, mesh, vao_b, PbrMaterial {
+            base_color_texture: texture(texture_a),
+            ..PbrMaterial::default()
+        });
+        let kept = add_primitive(&mut world, root, vao_a, PbrMaterial {
+            emissive_texture: texture(texture_b),
+            ..PbrMaterial::default()
+        });
+
+        let mut removed = remove_subtree(&world, &[mesh]);
+
+        //one entry per primitive and slot, the renderer decides what's still in use
+        removed.vao_ids.sort_by_key(|id| ids.iter().position(|other| other == id));
+        removed.texture_ids.sort_by_key(|id| ids.iter().position(|other| other == id));
+        assert_eq!(removed.vao_ids, vec![vao_a, vao_b]);
+        assert_eq!(removed.texture_ids, vec![texture_a, texture_a, texture_b]);
+
+        assert!(exists(&world, kept));
+        world.run::<(&Primitive, &PbrMaterial), _, _>(|(primitives, materials)| {
+            assert_eq!(primitives.iter().into_iter().count(), 1);
+            assert_eq!(materials.iter().into_iter().count(), 1);
+        });
+    }
+
+    #[test]
+    fn remove_node_detaches_from_parent() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 1.0, 0.0, 0.0);
+        let first = add(&mut world, Some(root), 0.0, 1.0, 0.0);
+        let middle = add(&mut world, Some(root), 0.0, 2.0, 0.0);
+        let last = add(&mut world, Some(root), 0.0, 3.0, 0.0);
+        let last_child = add(&mut world, Some(last), 0.0, 0.0, 1.0);
+
+        remove_subtree(&world, &[middle]);
+
+        assert_eq!(get_children(&world, root), vec![first, last]);
+        assert_eq!(get_parent(&world, last), Some(root));
+        assert_eq!(get_parent(&world, last_child), Some(last));
+
+        update_transforms(&world);
+        assert_position(&world, last_child, [1.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn set_parent_moves_subtree() {
+        let mut world = get_world();
+        let a = add(&mut world, None, 1.0, 0.0, 0.0);
+        let b = add(&mut world, None, 0.0, 10.0, 0.0);
+        let child = add(&mut world, Some(a), 0.0, 0.0, 1.0);
+        let grandchild = add(&mut world, Some(child), 0.0, 0.0, 1.0);
+        update_transforms(&world);
+
+        set_parent(&world, child, Some(b)).unwrap();
+
+        assert!(get_children(&world, a).is_empty());
+        assert_eq!(get_children(&world, b), vec![child]);
+        assert_eq!(get_parent(&world, child), Some(b));
+        update_transforms(&world);
+        assert_position(&world, child, [0.0, 10.0, 1.0]);
+        assert_position(&world, grandchild, [0.0, 10.0, 2.0]);
+
+        //and back out to a root
+        set_parent(&world, child, None).unwrap();
+
+        assert!(get_children(&world, b).is_empty());
+        assert_eq!(get_parent(&world, child), None);
+        update_transforms(&world);
+        assert_position(&world, grandchild, [0.0, 0.0, 2.0]);
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 0.0, 0.0, 0.0);
+        let child = add(&mut world, Some(root), 0.0, 0.0, 0.0);
+        let grandchild = add(&mut world, Some(child), 0.0, 0.0, 0.0);
+
+        for (node, parent) in &[(root, grandchild), (root, child), (child, child)] {
+            let result = set_parent(&world, *node, Some(*parent));
+            assert!(matches!(result, Err(Error::Native(NativeError::ParentCycle))));
+        }
+
+        //nothing changed
+        assert_eq!(get_parent(&world, root), None);
+        assert_eq!(get_children(&world, root), vec![child]);
+        assert_eq!(get_children(&world, child), vec![grandchild]);
+        assert!(get_children(&world, grandchild).is_empty());
+    }
+
+    #[test]
+    fn set_parent_missing_keys() {
+        let mut world = get_world();
+        let root = add(&mut world, None, 0.0, 0.0, 0.0);
+        let removed = add(&mut world, None, 0.0, 0.0, 0.0);
+        remove_subtree(&world, &[removed]);
+
+        assert!(matches!(set_parent(&world, root, Some(removed)), Err(Error::Native(NativeError::ParentMissing))));
+        assert!(matches!(set_parent(&world, removed, Some(root)), Err(Error::Native(NativeError::NodeKeyMissing))));
+        assert!(get_children(&world, root).is_empty());
+    }
+}
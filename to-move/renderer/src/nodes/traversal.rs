@@ -0,0 +1,297 @@
+use crate::renderer::Renderer;
+use super::nodes::{Node, Name, Tags, get_subtrees};
+use shipyard::prelude::*;
+use std::collections::HashSet;
+
+impl Renderer {
+    pub fn get_parent(&self, node:EntityId) -> Option<EntityId> {
+        get_parent(&self.world.borrow(), node)
+    }
+
+    pub fn get_children(&self, node:EntityId) -> Vec<EntityId> {
+        get_children(&self.world.borrow(), node)
+    }
+
+    /// Depth-first (pre-order), not including the node itself
+    pub fn get_descendants(&self, node:EntityId) -> Vec<EntityId> {
+        get_descendants(&self.world.borrow(), node)
+    }
+
+    /// From the parent up to the root
+    pub fn get_ancestors(&self, node:EntityId) -> Vec<EntityId> {
+        get_ancestors(&self.world.borrow(), node)
+    }
+
+    /// The top-most ancestor, or the node itself if it's a root
+    pub fn get_root(&self, node:EntityId) -> EntityId {
+        get_root(&self.world.borrow(), node)
+    }
+
+    /// All the nodes without a parent
+    pub fn get_roots(&self) -> Vec<EntityId> {
+        get_roots(&self.world.borrow())
+    }
+
+    pub fn get_name(&self, node:EntityId) -> Option<String> {
+        get_name(&self.world.borrow(), node)
+    }
+
+    pub fn set_name(&mut self, node:EntityId, name:&str) {
+        super::nodes::set_node_name(&mut self.world.borrow_mut(), node, name);
+    }
+
+    pub fn find_nodes_by_name(&self, name:&str) -> Vec<EntityId> {
+        find_nodes_by_name(&self.world.borrow(), name)
+    }
+
+    /// The first node found with this name
+    pub fn find_node_by_name(&self, name:&str) -> Option<EntityId> {
+        self.find_nodes_by_name(name).into_iter().next()
+    }
+
+    pub fn add_tag(&mut self, node:EntityId, tag:&str) {
+        add_tag(&self.world.borrow(), node, tag);
+    }
+
+    pub fn remove_tag(&mut self, node:EntityId, tag:&str) {
+        remove_tag(&self.world.borrow(), node, tag);
+    }
+
+    pub fn find_nodes_by_tag(&self, tag:&str) -> Vec<EntityId> {
+        find_nodes_by_tag(&self.world.borrow(), tag)
+    }
+}
+
+fn get_parent(world:&World, node:EntityId) -> Option<EntityId> {
+    world.run::<&Node, _, _>(|nodes| {
+        (&nodes).get(node).ok().and_then(|node| node.parent)
+    })
+}
+
+fn get_children(world:&World, node:EntityId) -> Vec<EntityId> {
+    world.run::<&Node, _, _>(|nodes| {
+        (&nodes).get(node).ok().map(|node| node.children.clone()).unwrap_or_default()
+    })
+}
+
+fn get_descendants(world:&World, node:EntityId) -> Vec<EntityId> {
+    world.run::<&Node, _, _>(|nodes| {
+        if (&nodes).get(node).is_err() {
+            return Vec::new();
+        }
+
+        //the walk starts with the node itself
+        let mut keys = get_subtrees(&nodes, &[node]);
+        keys.remove(0);
+        keys
+    })
+}
+
+fn get_ancestors(world:&World, node:EntityId) -> Vec<EntityId> {
+    world.run::<&Node, _, _>(|nodes| {
+        let mut keys = Vec::new();
+        let mut parent = (&nodes).get(node).ok().and_then(|node| node.parent);
+
+        while let Some(key) = parent {
+            keys.push(key);
+            parent = (&nodes).get(key).ok().and_then(|node| node.parent);
+        }
+
+        keys
+    })
+}
+
+fn get_root(world:&World, node:EntityId) -> EntityId {
+    get_ancestors(world, node).pop().unwrap_or(node)
+}
+
+fn get_roots(world:&World) -> Vec<EntityId> {
+    world.run::<&Node, _, _>(|nodes| {
+        nodes
+            .iter()
+            .with_id()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+fn get_name(world:&World, node:EntityId) -> Option<String> {
+    world.run::<&Name, _, _>(|names| {
+        (&names).get(node).ok().map(|name| name.0.clone())
+    })
+}
+
+fn find_nodes_by_name(world:&World, name:&str) -> Vec<EntityId> {
+    world.run::<&Name, _, _>(|names| {
+        names
+            .iter()
+            .with_id()
+            .filter(|(_, other)| other.0 == name)
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+fn add_tag(world:&World, node:EntityId, tag:&str) {
+    world.run::<(EntitiesMut, &mut Tags), _, _>(|(entities, mut tags)| {
+        match (&mut tags).get(node).ok() {
+            Some(tags) => {
+                tags.0.insert(tag.to_string());
+            },
+            None => {
+                let mut set = HashSet::new();
+                set.insert(tag.to_string());
+                entities.add_component(&mut tags, Tags(set), node);
+            }
+        }
+    });
+}
+
+fn remove_tag(world:&World, node:EntityId, tag:&str) {
+    world.run::<&mut Tags, _, _>(|mut tags| {
+        if let Ok(tags) = (&mut tags).get(node) {
+            tags.0.remove(tag);
+        }
+    });
+}
+
+fn find_nodes_by_tag(world:&World, tag:&str) -> Vec<EntityId> {
+    world.run::<&Tags, _, _>(|tags| {
+        tags
+            .iter()
+            .with_id()
+            .filter(|(_, tags)| tags.0.contains(tag))
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::{add_node, set_node_name, remove_subtree, NodeData};
+
+    fn add(world:&mut World, parent:Option<EntityId>) -> EntityId {
+        add_node(world, NodeData::Empty, parent, None, None, None).unwrap()
+    }
+
+    //EntityId isn't Ord, and the storage order isn't the insertion order anyway
+    fn unordered(keys:Vec<EntityId>) -> HashSet<EntityId> {
+        keys.into_iter().collect()
+    }
+
+    //root
+    // - a
+    //   - a_1
+    //   - a_2
+    //     - a_2_1
+    // - b
+    struct Tree {
+        root: EntityId,
+        a: EntityId,
+        a_1: EntityId,
+        a_2: EntityId,
+        a_2_1: EntityId,
+        b: EntityId,
+    }
+
+    fn get_tree(world:&mut World) -> Tree {
+        let root = add(world, None);
+        let a = add(world, Some(root));
+        let a_1 = add(world, Some(a));
+        let a_2 = add(world, Some(a));
+        let a_2_1 = add(world, Some(a_2));
+        let b = add(world, Some(root));
+
+        Tree { root, a, a_1, a_2, a_2_1, b }
+    }
+
+    #[test]
+    fn descendants_are_pre_order() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+
+        assert_eq!(get_descendants(&world, tree.root), vec![tree.a, tree.a_1, tree.a_2, tree.a_2_1, tree.b]);
+        assert_eq!(get_descendants(&world, tree.a_2), vec![tree.a_2_1]);
+        assert!(get_descendants(&world, tree.b).is_empty());
+    }
+
+    #[test]
+    fn descendants_of_missing_node() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+        remove_subtree(&world, &[tree.a]);
+
+        assert!(get_descendants(&world, tree.a).is_empty());
+        assert_eq!(get_descendants(&world, tree.root), vec![tree.b]);
+    }
+
+    #[test]
+    fn ancestors_and_root() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+
+        assert_eq!(get_ancestors(&world, tree.a_2_1), vec![tree.a_2, tree.a, tree.root]);
+        assert!(get_ancestors(&world, tree.root).is_empty());
+
+        assert_eq!(get_root(&world, tree.a_2_1), tree.root);
+        assert_eq!(get_root(&world, tree.b), tree.root);
+        assert_eq!(get_root(&world, tree.root), tree.root);
+
+        assert_eq!(get_parent(&world, tree.a_1), Some(tree.a));
+        assert_eq!(get_parent(&world, tree.root), None);
+        assert_eq!(get_children(&world, tree.a), vec![tree.a_1, tree.a_2]);
+    }
+
+    #[test]
+    fn roots() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+        let other = add(&mut world, None);
+
+        assert_eq!(unordered(get_roots(&world)), unordered(vec![tree.root, other]));
+
+        remove_subtree(&world, &[tree.root]);
+        assert_eq!(get_roots(&world), vec![other]);
+    }
+
+    #[test]
+    fn names() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+        set_node_name(&mut world, tree.a, "arm");
+        set_node_name(&mut world, tree.b, "arm");
+        set_node_name(&mut world, tree.a_1, "hand");
+
+        assert_eq!(get_name(&world, tree.a_1), Some("hand".to_string()));
+        assert_eq!(get_name(&world, tree.root), None);
+        assert_eq!(unordered(find_nodes_by_name(&world, "arm")), unordered(vec![tree.a, tree.b]));
+        assert!(find_nodes_by_name(&world, "leg").is_empty());
+
+        //renaming replaces it
+        set_node_name(&mut world, tree.a_1, "claw");
+        assert!(find_nodes_by_name(&world, "hand").is_empty());
+        assert_eq!(find_nodes_by_name(&world, "claw"), vec![tree.a_1]);
+    }
+
+    #[test]
+    fn tags() {
+        let mut world = World::default();
+        let tree = get_tree(&mut world);
+        add_tag(&world, tree.a, "enemy");
+        add_tag(&world, tree.a, "flying");
+        add_tag(&world, tree.a, "enemy");
+        add_tag(&world, tree.b, "enemy");
+
+        assert_eq!(unordered(find_nodes_by_tag(&world, "enemy")), unordered(vec![tree.a, tree.b]));
+        assert_eq!(find_nodes_by_tag(&world, "flying"), vec![tree.a]);
+
+        remove_tag(&world, tree.a, "enemy");
+        //removing what isn't there does nothing
+        remove_tag(&world, tree.root, "enemy");
+
+        assert_eq!(find_nodes_by_tag(&world, "enemy"), vec![tree.b]);
+        assert_eq!(find_nodes_by_tag(&world, "flying"), vec![tree.a]);
+    }
+}
@@ -1,12 +1,17 @@
-use awsm_web::webgl::{Id, DataType, BeginMode};
+use awsm_web::webgl::{Id, DataType, BeginMode, WebGl2Renderer, AttributeOptions, BufferTarget};
+use crate::errors::Error;
+use crate::renderer::Renderer;
 use shipyard::prelude::*;
+use web_sys::WebGl2RenderingContext;
 
+#[derive(Clone)]
 pub struct Primitive {
     pub shader_id: Id,
     pub vao_id: Id,
     pub draw_info:PrimitiveDraw
 } 
 
+#[derive(Clone)]
 pub enum PrimitiveDraw {
     //count, DataType, offset
     Elements(BeginMode, u32, DataType, u32),
@@ -14,3 +19,125 @@ pub enum PrimitiveDraw {
     Direct(BeginMode, u32, u32)
 }
 
+/// A buffer bound to a fixed attribute location (see shaders::ATTRIBUTE_LOCATIONS)
+pub(crate) struct VertexAttribute {
+    pub location: u32,
+    pub buffer_id: Id,
+    pub opts: AttributeOptions,
+}
+
+/// Keeps track of the vaos created for primitives and the buffers they use
+/// awsm_web can't delete a vertex array object, so released ones are
+/// kept in a free list and handed out again instead of creating new ones
+#[derive(Default)]
+pub(crate) struct VertexArrays {
+    //vao -> the buffers it uses, so they can be released when nothing uses them
+    buffers: Vec<(Id, Vec<Id>)>,
+    free: Vec<Id>,
+}
+
+impl VertexArrays {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reuses a released vao if there is one
+    /// its attributes are disabled so that only the ones assigned next are used
+    pub fn create(&mut self, webgl:&mut WebGl2Renderer) -> Result<Id, Error> {
+        match self.free.pop() {
+            Some(vao_id) => {
+                webgl.activate_vertex_array(vao_id)?;
+                let max_attributes = webgl.gl
+                    .get_parameter(WebGl2RenderingContext::MAX_VERTEX_ATTRIBS)?
+                    .as_f64()
+                    .unwrap_or(0.0) as u32;
+                for location in 0..max_attributes {
+                    webgl.gl.disable_vertex_attrib_array(location);
+                }
+                webgl.release_vertex_array()?;
+                Ok(vao_id)
+            },
+            None => Ok(webgl.create_vertex_array()?)
+        }
+    }
+
+    /// Binds the buffers by location rather than by looking up names in the active program
+    /// so the same vao works with the built-in shader and with custom materials
+    pub fn assign(&mut self, webgl:&mut WebGl2Renderer, vao_id:Id, element_buffer_id:Option<Id>, attributes:&[VertexAttribute]) -> Result<(), Error> {
+        webgl.activate_vertex_array(vao_id)?;
+
+        //the buffer binding cache doesn't know that the vao changed
+        //so release first to make sure the bind goes through
+        if let Some(element_buffer_id) = element_buffer_id {
+            webgl.release_buffer(BufferTarget::ElementArrayBuffer);
+            webgl.bind_buffer(element_buffer_id, BufferTarget::ElementArrayBuffer)?;
+        }
+
+        for attribute in attributes {
+            webgl.release_buffer(BufferTarget::ArrayBuffer);
+            webgl.bind_buffer(attribute.buffer_id, BufferTarget::ArrayBuffer)?;
+            webgl.activate_attribute_loc(attribute.location, &attribute.opts);
+        }
+
+        webgl.release_vertex_array()?;
+
+        let mut buffer_ids:Vec<Id> = attributes.iter().map(|attribute| attribute.buffer_id).collect();
+        buffer_ids.extend(element_buffer_id);
+        self.buffers.push((vao_id, buffer_ids));
+
+        Ok(())
+    }
+
+    /// Puts the vao on the free list and returns the buffers it was using
+    pub fn release(&mut self, vao_id:Id) -> Vec<Id> {
+        self.free.push(vao_id);
+        match self.buffers.iter().position(|(other, _)| *other == vao_id) {
+            Some(index) => self.buffers.swap_remove(index).1,
+            None => Vec::new()
+        }
+    }
+
+    pub fn uses_buffer(&self, buffer_id:Id) -> bool {
+        self.buffers.iter().any(|(_, buffer_ids)| buffer_ids.contains(&buffer_id))
+    }
+}
+
+impl Renderer {
+    /// Releases the vaos (and deletes their buffers) that aren't used by any primitive in the world
+    /// or kept around for a shared gltf resource
+    pub(crate) fn release_vertex_arrays(&mut self, vao_ids:Vec<Id>) -> Result<(), Error> {
+        let world = self.world.borrow();
+        let webgl = self.webgl.borrow_mut();
+
+        let used_vao_ids:Vec<Id> = world.run::<&Primitive, _, _>(|primitives| {
+            primitives.iter().map(|primitive| primitive.vao_id).collect()
+        });
+
+        let mut unused_vao_ids:Vec<Id> = Vec::new();
+        for vao_id in vao_ids {
+            if !used_vao_ids.contains(&vao_id) 
+                && !unused_vao_ids.contains(&vao_id)
+                && !self.gltf_uploads.iter().any(|(_, uploads)| uploads.uses_vertex_array(vao_id)) {
+                unused_vao_ids.push(vao_id);
+            }
+        }
+
+        let mut buffer_ids:Vec<Id> = Vec::new();
+        for vao_id in unused_vao_ids {
+            for buffer_id in self.vertex_arrays.release(vao_id) {
+                if !buffer_ids.contains(&buffer_id) {
+                    buffer_ids.push(buffer_id);
+                }
+            }
+        }
+
+        //buffers can be shared between vaos
+        for buffer_id in buffer_ids {
+            if !self.vertex_arrays.uses_buffer(buffer_id) {
+                webgl.delete_buffer(buffer_id)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
 use std::rc::Rc;
 use std::cell::RefCell;
-use awsm_web::webgl::{ WebGl2Renderer, ClearBufferMask, BufferData, BufferTarget, BufferUsage, Id};
-use crate::errors::{Error, NativeError};
+use awsm_web::webgl::{ WebGl2Renderer, BufferMask, Id};
+use crate::errors::{Error, NativeError, allow_missing_uniform};
 use crate::gltf::loader::GltfResource;
 use crate::components::*;
-use crate::primitives::PrimitiveDraw;
-use crate::gltf::processor::{ProcessState, process_scene};
+use crate::primitives::{PrimitiveDraw, VertexArrays};
+use crate::shaders::{ShaderCache, ShaderCacheStats};
+use crate::textures::TextureManager;
+use web_sys::WebGl2RenderingContext;
+use crate::nodes::update_transforms;
+use crate::materials::{CustomMaterialDef, upload_material_params, upload_pbr_material};
+use crate::gltf::processor::{ProcessState, GltfUploads, GltfSceneKeys, process_scene};
 
 use shipyard::prelude::*;
 
@@ -18,6 +23,16 @@ pub struct Renderer {
     pub world: Rc<RefCell<World>>,
 
     pub(crate) camera_buffer_id: Id,
+    pub(crate) lights_buffer_id: Id,
+    //GPU data for resources uploaded via upload_shared_gltf
+    //holding the Rc keeps the pointer stable for comparison
+    pub(crate) gltf_uploads: Vec<(Rc<GltfResource>, GltfUploads)>,
+    pub(crate) vertex_arrays: VertexArrays,
+    pub(crate) shader_cache: ShaderCache,
+    pub(crate) custom_materials: Vec<CustomMaterialDef>,
+    pub(crate) textures: TextureManager,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
 }
 
 impl Renderer {
@@ -28,7 +43,8 @@ impl Renderer {
         };
 
         let camera_buffer_id = webgl.borrow_mut().create_buffer()?;
-        let mut ret = Self{webgl, world, camera_buffer_id};
+        let lights_buffer_id = webgl.borrow_mut().create_buffer()?;
+        let mut ret = Self{webgl, world, camera_buffer_id, lights_buffer_id, gltf_uploads: Vec::new(), vertex_arrays: VertexArrays::new(), shader_cache: ShaderCache::new(), custom_materials: Vec::new(), textures: TextureManager::new(), width, height};
 
         {
             let mut world = ret.world.borrow_mut();
@@ -40,77 +56,127 @@ impl Renderer {
         Ok(ret)
     }
 
+    /// How many programs were compiled vs. reused
+    pub fn shader_cache_stats(&self) -> ShaderCacheStats {
+        self.shader_cache.stats().clone()
+    }
+
+    /// Makes the source available to shaders via #include "name"
+    /// Overrides the built-in chunk of the same name
+    /// Programs that were already compiled aren't rebuilt
+    pub fn register_shader_chunk(&mut self, name:&str, source:&str) {
+        self.shader_cache.chunks_mut().register(name, source);
+    }
+
+    /// Cameras with a lens get their aspect ratio updated
     pub fn resize(&mut self, width: u32, height: u32) {
-        let mut webgl = self.webgl.borrow_mut();
-        webgl.resize(width, height);
+        self.webgl.borrow_mut().resize(width, height);
+        self.width = width;
+        self.height = height;
+        self.update_camera_lenses();
     }
 
     pub fn clear(&mut self) {
         let webgl = self.webgl.borrow_mut();
 
         webgl.clear(&[
-            ClearBufferMask::ColorBufferBit,
-            ClearBufferMask::DepthBufferBit,
+            BufferMask::ColorBufferBit,
+            BufferMask::DepthBufferBit,
         ]);
     }
 
 
     fn update_transforms(&mut self) {
-        let mut webgl = self.webgl.borrow_mut();
-        let world = self.world.borrow_mut();
-
-        //Update all the LocalMatrices
-        world.run::<(&Translation, &Rotation, &Scale, &mut LocalTransform), _, _>(|(translations, rotations, scales, local_matrices)| {
-            for (translation, rotation, scale, mut local_matrix) in (translations, rotations, scales, local_matrices).iter() {
-                let local_matrix = &mut local_matrix.0;
-                let translation = &translation.0;
-                let rotation = &rotation.0;
-                let scale = &scale.0;
-                local_matrix.from_trs_mut(translation, rotation, scale);
-            }
-        });
-
-        //Update all the WorldMatrices
-        world.run::<(&Node, &LocalTransform, &mut WorldTransform), _, _>(|(nodes, local_matrices, world_matrices)| {
-            let mut parent_matrix = Matrix4::default();
-            let mut child_index = 0;
-            for (node, local_matrix, world_matrix) in (nodes, local_matrices, world_matrices).iter() {
-                let local_matrix = &local_matrix.0;
-                let world_matrix = &mut world_matrix.0;
-                world_matrix.copy_from(&local_matrix);
-                /*
-                world_matrix.copy_from(&parent_matrix);
-                world_matrix.mul_mut(local_matrix);
-
-                if child_index == node.n_children {
-                    parent_matrix.copy_from(world_matrix);
-                    child_index = 0;
-                } else {
-                    child_index += 1;
-                }
-                */
-            }
-        });
+        update_transforms(&self.world.borrow());
     }
 
-    pub fn render(&mut self, _interpolation:Option<f64>) {
+    /// Renders each of the active cameras into its viewport
+    pub fn render(&mut self, _interpolation:Option<f64>) -> Result<(), Error> {
         self.update_transforms();
-        //mult-camera support will require changing this to Some
-        //idea - have a Unique component which holds the active camera
-        self.update_camera_view(None);
-        self.update_camera_ubo(None);
+        self.update_lights_ubo()?;
+
+        let camera_viewports = self.get_camera_viewports();
+
+        if camera_viewports.is_empty() {
+            return self.draw_primitives();
+        }
+
+        for (index, camera_viewport) in camera_viewports.iter().enumerate() {
+            let CameraViewport { camera, viewport } = camera_viewport;
+            let aspect_ratio = viewport.aspect_ratio(self.width, self.height);
+
+            self.update_camera_view(Some(*camera))?;
+            self.update_camera_ubo(Some(*camera), Some(aspect_ratio))?;
+            self.set_viewport(viewport, index > 0);
+            self.draw_primitives()?;
+        }
+
+        self.set_viewport(&Viewport::full(), false);
+
+        Ok(())
+    }
+
+    /// Restricts drawing (and clearing) to the viewport
+    /// clear_depth is for drawing over what's already there
+    fn set_viewport(&mut self, viewport:&Viewport, clear_depth: bool) {
+        let webgl = self.webgl.borrow_mut();
+        let gl = &webgl.gl;
+        let (x, y, width, height) = viewport.get_pixels(self.width, self.height);
+
+        gl.viewport(x, y, width, height);
 
+        if *viewport == Viewport::full() {
+            gl.disable(WebGl2RenderingContext::SCISSOR_TEST);
+        } else {
+            gl.enable(WebGl2RenderingContext::SCISSOR_TEST);
+            gl.scissor(x, y, width, height);
+        }
+
+        if clear_depth {
+            webgl.clear(&[BufferMask::DepthBufferBit]);
+        }
+    }
+
+    fn draw_primitives(&mut self) -> Result<(), Error> {
         let mut webgl = self.webgl.borrow_mut();
         let world = self.world.borrow_mut();
 
-        world.run::<(&Primitive, &WorldTransform), _, _>(|(primitives, model_matrices)| {
-            for (primitive, model_matrix) in (primitives, model_matrices).iter() {
+        world.run::<(&Primitive, &WorldTransform, &CustomMaterial, &PbrMaterial), _, _>(|(primitives, model_matrices, custom_materials, pbr_materials)| {
+            for (key, (primitive, model_matrix)) in (&primitives, &model_matrices).iter().with_id().into_iter() {
                 let Primitive{shader_id, vao_id, draw_info} = primitive;
 
-                webgl.activate_program(*shader_id).unwrap();
-                webgl.activate_uniform_buffer(self.camera_buffer_id, "camera").unwrap();
-                webgl.upload_uniform_mat_4("u_model", &model_matrix.0.to_vec_f32()).unwrap();
-                webgl.activate_vertex_array(*vao_id).unwrap();
+                let custom = (&custom_materials)
+                    .get(key)
+                    .ok()
+                    .and_then(|custom_material| {
+                        self.custom_materials
+                            .get(custom_material.id.0)
+                            .map(|def| (def, custom_material))
+                    });
+
+                match custom {
+                    Some((def, custom_material)) => {
+                        //custom shaders don't have to use the camera or model
+                        //and unused uniforms are stripped by the compiler
+                        webgl.activate_program(def.program_id)?;
+                        allow_missing_uniform(webgl.activate_uniform_buffer(self.camera_buffer_id, "camera"))?;
+                        allow_missing_uniform(webgl.activate_uniform_buffer(self.lights_buffer_id, "lights"))?;
+                        allow_missing_uniform(webgl.upload_uniform_mat_4("u_model", &model_matrix.0.to_vec_f32()))?;
+                        upload_material_params(&mut webgl, def, custom_material)?;
+                    },
+                    None => {
+                        webgl.activate_program(*shader_id)?;
+                        webgl.activate_uniform_buffer(self.camera_buffer_id, "camera")?;
+                        webgl.activate_uniform_buffer(self.lights_buffer_id, "lights")?;
+                        webgl.upload_uniform_mat_4("u_model", &model_matrix.0.to_vec_f32())?;
+                        match (&pbr_materials).get(key).ok() {
+                            Some(material) => upload_pbr_material(&mut webgl, material)?,
+                            None => upload_pbr_material(&mut webgl, &PbrMaterial::default())?
+                        }
+                    }
+                }
+
+                webgl.activate_vertex_array(*vao_id)?;
 
                 match draw_info {
                     PrimitiveDraw::Elements(draw_mode, count, data_type, offset) => {
@@ -122,7 +188,9 @@ impl Renderer {
                     }
                 };
             }
-        });
+
+            Ok(())
+        })
     }
 
     pub fn animate(&mut self, _delta:f64) {
@@ -134,7 +202,76 @@ impl Renderer {
     //2. default scene set in gltf
     //3. first in scenes array
     //if none of these exist, it's an error (not supporting gltf as asset library atm)
-    pub fn upload_gltf(&mut self, resource:&GltfResource, scene:Option<gltf::scene::Scene>) -> Result<(), Error> {
+    //
+    //Every gltf node becomes a node in the ECS, with primitives as children of their mesh's node
+    pub fn upload_gltf(&mut self, resource:&GltfResource, scene:Option<gltf::scene::Scene>) -> Result<GltfSceneKeys, Error> {
+        let mut uploads = GltfUploads::new(resource);
+        let result = self.upload_gltf_with(resource, &mut uploads, scene);
+
+        //the uploads aren't kept, so only the primitives' uses of the textures are left
+        self.release_textures(uploads.texture_ids.iter().flatten().copied().collect())?;
+
+        result
+    }
+
+    /// Same as upload_gltf, but the GPU buffers and programs are kept around
+    /// so that uploading the same resource again (e.g. from a GltfCache) reuses them
+    /// The renderer holds a reference to the resource until release_shared_gltf() is called
+    pub fn upload_shared_gltf(&mut self, resource:&Rc<GltfResource>, scene:Option<gltf::scene::Scene>) -> Result<GltfSceneKeys, Error> {
+        let index = match self.gltf_uploads.iter().position(|(other, _)| Rc::ptr_eq(resource, other)) {
+            Some(index) => index,
+            None => {
+                self.gltf_uploads.push((Rc::clone(resource), GltfUploads::new(resource)));
+                self.gltf_uploads.len() - 1
+            }
+        };
+
+        //take it out temporarily so we can borrow self mutably
+        let (resource, mut uploads) = self.gltf_uploads.swap_remove(index);
+        let result = self.upload_gltf_with(&resource, &mut uploads, scene);
+        self.gltf_uploads.push((resource, uploads));
+        result
+    }
+
+    /// Stops keeping track of the shared resource
+    /// The GPU data stays alive for nodes that are already using it
+    /// and is released once they're removed
+    pub fn release_shared_gltf(&mut self, resource:&Rc<GltfResource>) -> Result<(), Error> {
+        let released:Vec<GltfUploads> = {
+            let (released, kept) = self.gltf_uploads
+                .drain(..)
+                .partition(|(other, _)| Rc::ptr_eq(resource, other));
+            self.gltf_uploads = kept;
+            released.into_iter().map(|(_, uploads)| uploads).collect()
+        };
+
+        let vao_ids:Vec<Id> = released
+            .iter()
+            .flat_map(|uploads| uploads.mesh_primitives())
+            .map(|primitive| primitive.vao_id)
+            .collect();
+
+        let texture_ids:Vec<Id> = released
+            .iter()
+            .flat_map(|uploads| uploads.texture_ids.iter().flatten().copied())
+            .collect();
+
+        self.release_textures(texture_ids)?;
+        self.release_vertex_arrays(vao_ids)
+    }
+
+    fn upload_gltf_with(&mut self, resource:&GltfResource, uploads:&mut GltfUploads, scene:Option<gltf::scene::Scene>) -> Result<GltfSceneKeys, Error> {
+        let keys = self.process_gltf_scene(resource, uploads, scene)?;
+
+        //imported cameras are created without knowing the viewport
+        if !keys.cameras.is_empty() {
+            self.update_camera_lenses();
+        }
+
+        Ok(keys)
+    }
+
+    fn process_gltf_scene(&mut self, resource:&GltfResource, uploads:&mut GltfUploads, scene:Option<gltf::scene::Scene>) -> Result<GltfSceneKeys, Error> {
         let mut webgl = self.webgl.borrow_mut();
         let mut world = self.world.borrow_mut();
 
@@ -145,41 +282,9 @@ impl Renderer {
                 )
         ).ok_or(NativeError::SceneMissing)?;
 
-        process_scene(ProcessState::new(resource,&mut world,&mut webgl), &scene)?;
-
-
+        let keys = process_scene(ProcessState::new(resource,&mut world,&mut webgl, uploads, &mut self.vertex_arrays, &mut self.shader_cache, &mut self.textures), &scene)?;
 
-            /*
-            if let Some(mesh) = node.mesh() {
-                mesh.primitives().any(|primitive| {
-
-                    if primitive.indices().map(|acc| acc.index()).contains(&accessor_id) {
-                        return true;
-                    }
-                    if primitive.attributes().any(|(_, attribute_accessor)| {
-                        attribute_accessor.index() == accessor_id
-                    }) {
-                        return true;
-                    }
-                    if primitive.morph_targets().any(|morph_target| {
-                        morph_target.positions().map(|acc| acc.index()).contains(&accessor_id) 
-                            || morph_target.normals().map(|acc| acc.index()).contains(&accessor_id) 
-                            || morph_target.tangents().map(|acc| acc.index()).contains(&accessor_id)
-                    }) {
-                        return true;
-                    }
-
-                    false
-                })
-            } else {
-                false
-            }
-            */
-        //let mut buffer_ids = gltf_renderer::buffer_view::upload_buffer_views(&mut webgl, &gltf, &buffers)?;
-        //gltf_renderer::accessors::populate_accessors(&mut webgl, &mut world, &gltf, &mut buffer_ids, &buffers);
-        //gltf_renderer::accessors::upload_accessors(&mut webgl, &gltf, buffers)?;
-
-        Ok(())
+        Ok(keys)
     }
 
     pub fn set_scene_from_gltf(&mut self, _gltf:&gltf::Document) {
@@ -0,0 +1,5 @@
+//explicit precision since it's shared by the vertex and fragment shaders
+layout (std140) uniform camera {
+    uniform highp mat4 u_view;
+    uniform highp mat4 u_projection;
+};
@@ -0,0 +1,60 @@
+//Packed by lights/lights.rs
+#define MAX_LIGHTS 8
+
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+struct Light {
+    //xyz is the world position, w is the range (0 for unlimited)
+    vec4 position_range;
+    //xyz is the direction the light points in, w is the type
+    vec4 direction_type;
+    vec4 color_intensity;
+    //x is the scale and y the offset for the spot cone falloff
+    vec4 spot;
+};
+
+layout (std140) uniform lights {
+    //x is the number of lights
+    vec4 u_light_count;
+    Light u_lights[MAX_LIGHTS];
+};
+
+//see https://github.com/KhronosGroup/glTF/tree/master/extensions/2.0/Khronos/KHR_lights_punctual#range-property
+float get_range_attenuation(float range, float distance) {
+    float inverse_square = 1.0 / max(distance * distance, 0.0001);
+    if(range <= 0.0) {
+        return inverse_square;
+    }
+    return clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0) * inverse_square;
+}
+
+float get_spot_attenuation(Light light, vec3 to_light) {
+    float cos_angle = dot(light.direction_type.xyz, -to_light);
+    float attenuation = clamp(cos_angle * light.spot.x + light.spot.y, 0.0, 1.0);
+    return attenuation * attenuation;
+}
+
+//the direction towards the light, and how much of it reaches the point
+void get_light(Light light, vec3 position, out vec3 to_light, out vec3 radiance) {
+    int light_type = int(light.direction_type.w);
+    vec3 intensity = light.color_intensity.rgb * light.color_intensity.a;
+
+    if(light_type == LIGHT_DIRECTIONAL) {
+        to_light = -light.direction_type.xyz;
+        radiance = intensity;
+        return;
+    }
+
+    vec3 offset = light.position_range.xyz - position;
+    float distance = length(offset);
+    to_light = offset / max(distance, 0.0001);
+
+    float attenuation = get_range_attenuation(light.position_range.w, distance);
+    if(light_type == LIGHT_SPOT) {
+        attenuation *= get_spot_attenuation(light, to_light);
+    }
+
+    radiance = intensity * attenuation;
+}
@@ -0,0 +1,139 @@
+//PbrMaterial params, see materials/pbr.rs
+uniform vec4 u_base_color_factor;
+uniform float u_metallic_factor;
+uniform float u_roughness_factor;
+uniform vec3 u_emissive_factor;
+uniform float u_normal_scale;
+uniform float u_occlusion_strength;
+uniform float u_alpha_cutoff;
+
+#ifdef HAS_BASE_COLOR_MAP
+uniform sampler2D u_base_color_sampler;
+uniform int u_base_color_texcoord;
+#endif
+
+#ifdef HAS_METALLIC_ROUGHNESS_MAP
+uniform sampler2D u_metallic_roughness_sampler;
+uniform int u_metallic_roughness_texcoord;
+#endif
+
+#ifdef HAS_NORMAL_MAP
+uniform sampler2D u_normal_sampler;
+uniform int u_normal_texcoord;
+#endif
+
+#ifdef HAS_OCCLUSION_MAP
+uniform sampler2D u_occlusion_sampler;
+uniform int u_occlusion_texcoord;
+#endif
+
+#ifdef HAS_EMISSIVE_MAP
+uniform sampler2D u_emissive_sampler;
+uniform int u_emissive_texcoord;
+#endif
+
+vec2 get_texcoord(int set) {
+    #ifdef HAS_UV_1
+    if(set == 1) {
+        return v_texcoord_1;
+    }
+    #endif
+
+    #ifdef HAS_UV_0
+    return v_texcoord_0;
+    #else
+    return vec2(0.0, 0.0);
+    #endif
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    return pow(color, vec3(2.2));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return pow(color, vec3(1.0 / 2.2));
+}
+
+vec4 get_base_color() {
+    vec4 color = u_base_color_factor;
+
+    #ifdef HAS_BASE_COLOR_MAP
+    vec4 texel = texture(u_base_color_sampler, get_texcoord(u_base_color_texcoord));
+    color *= vec4(srgb_to_linear(texel.rgb), texel.a);
+    #endif
+
+    #ifdef HAS_COLOR_0
+    color *= v_color_0;
+    #endif
+
+    return color;
+}
+
+//x is metallic, y is roughness
+vec2 get_metallic_roughness() {
+    vec2 value = vec2(u_metallic_factor, u_roughness_factor);
+
+    #ifdef HAS_METALLIC_ROUGHNESS_MAP
+    //metalness is in the blue channel, roughness in green
+    vec4 texel = texture(u_metallic_roughness_sampler, get_texcoord(u_metallic_roughness_texcoord));
+    value *= texel.bg;
+    #endif
+
+    return clamp(value, vec2(0.0, 0.04), vec2(1.0, 1.0));
+}
+
+float get_occlusion() {
+    #ifdef HAS_OCCLUSION_MAP
+    float occlusion = texture(u_occlusion_sampler, get_texcoord(u_occlusion_texcoord)).r;
+    return 1.0 + u_occlusion_strength * (occlusion - 1.0);
+    #else
+    return 1.0;
+    #endif
+}
+
+vec3 get_emissive() {
+    vec3 emissive = u_emissive_factor;
+
+    #ifdef HAS_EMISSIVE_MAP
+    emissive *= srgb_to_linear(texture(u_emissive_sampler, get_texcoord(u_emissive_texcoord)).rgb);
+    #endif
+
+    return emissive;
+}
+
+vec3 get_normal() {
+    #ifdef HAS_NORMALS
+    vec3 normal = normalize(v_normal);
+    #else
+    //flat shading from the screen-space derivatives
+    vec3 normal = normalize(cross(dFdx(v_position), dFdy(v_position)));
+    #endif
+
+    //double-sided materials show their back faces, which point the other way
+    if(!gl_FrontFacing) {
+        normal = -normal;
+    }
+
+    #ifdef HAS_NORMAL_MAP
+    vec2 uv = get_texcoord(u_normal_texcoord);
+
+    #ifdef HAS_TANGENTS
+    vec3 tangent = normalize(v_tangent.xyz);
+    vec3 bitangent = cross(normal, tangent) * v_tangent.w;
+    #else
+    //derive the tangent frame from the texcoord derivatives
+    vec3 dp_dx = dFdx(v_position);
+    vec3 dp_dy = dFdy(v_position);
+    vec2 duv_dx = dFdx(uv);
+    vec2 duv_dy = dFdy(uv);
+    vec3 tangent = normalize(dp_dx * duv_dy.t - dp_dy * duv_dx.t);
+    vec3 bitangent = normalize(cross(normal, tangent));
+    #endif
+
+    vec3 sampled = texture(u_normal_sampler, uv).rgb * 2.0 - 1.0;
+    sampled.xy *= u_normal_scale;
+    normal = normalize(mat3(tangent, bitangent, normal) * sampled);
+    #endif
+
+    return normal;
+}
@@ -0,0 +1,58 @@
+//Cook-Torrance with the usual glTF choices:
+//GGX distribution, height-correlated Smith visibility and Schlick fresnel
+//see https://github.com/KhronosGroup/glTF/tree/master/specification/2.0#appendix-b-brdf-implementation
+
+const float PI = 3.141592653589793;
+
+struct PbrInfo {
+    vec3 diffuse_color;
+    vec3 specular_color;
+    float alpha_roughness;
+};
+
+PbrInfo get_pbr_info(vec3 base_color, float metallic, float roughness) {
+    vec3 f0 = vec3(0.04);
+
+    PbrInfo info;
+    info.diffuse_color = mix(base_color * (1.0 - f0), vec3(0.0), metallic);
+    info.specular_color = mix(f0, base_color, metallic);
+    info.alpha_roughness = roughness * roughness;
+    return info;
+}
+
+vec3 fresnel_schlick(vec3 f0, float v_dot_h) {
+    return f0 + (vec3(1.0) - f0) * pow(clamp(1.0 - v_dot_h, 0.0, 1.0), 5.0);
+}
+
+float visibility_smith_ggx(float n_dot_l, float n_dot_v, float alpha_roughness) {
+    float alpha_sq = alpha_roughness * alpha_roughness;
+    float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha_sq) + alpha_sq);
+    float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha_sq) + alpha_sq);
+    float ggx = ggx_v + ggx_l;
+    return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+float distribution_ggx(float n_dot_h, float alpha_roughness) {
+    float alpha_sq = alpha_roughness * alpha_roughness;
+    float f = (n_dot_h * n_dot_h) * (alpha_sq - 1.0) + 1.0;
+    return alpha_sq / (PI * f * f);
+}
+
+//outgoing radiance for one light (before multiplying by the light's intensity)
+vec3 get_brdf(PbrInfo info, vec3 normal, vec3 view, vec3 light) {
+    vec3 half_vector = normalize(light + view);
+
+    float n_dot_l = clamp(dot(normal, light), 0.0, 1.0);
+    float n_dot_v = clamp(abs(dot(normal, view)), 0.001, 1.0);
+    float n_dot_h = clamp(dot(normal, half_vector), 0.0, 1.0);
+    float v_dot_h = clamp(dot(view, half_vector), 0.0, 1.0);
+
+    vec3 f = fresnel_schlick(info.specular_color, v_dot_h);
+    float vis = visibility_smith_ggx(n_dot_l, n_dot_v, info.alpha_roughness);
+    float d = distribution_ggx(n_dot_h, info.alpha_roughness);
+
+    vec3 diffuse = (vec3(1.0) - f) * (info.diffuse_color / PI);
+    vec3 specular = f * vis * d;
+
+    return n_dot_l * (diffuse + specular);
+}
@@ -0,0 +1,21 @@
+//the joint attributes are declared by the vertex shader (see HAS_JOINTS_n)
+
+uniform mat4 u_joint_matrices[MAX_JOINTS];
+
+mat4 get_skin_matrix() {
+    mat4 skin = 
+        a_weights_0.x * u_joint_matrices[int(a_joints_0.x)] +
+        a_weights_0.y * u_joint_matrices[int(a_joints_0.y)] +
+        a_weights_0.z * u_joint_matrices[int(a_joints_0.z)] +
+        a_weights_0.w * u_joint_matrices[int(a_joints_0.w)];
+
+    #ifdef HAS_JOINTS_1
+    skin += 
+        a_weights_1.x * u_joint_matrices[int(a_joints_1.x)] +
+        a_weights_1.y * u_joint_matrices[int(a_joints_1.y)] +
+        a_weights_1.z * u_joint_matrices[int(a_joints_1.z)] +
+        a_weights_1.w * u_joint_matrices[int(a_joints_1.w)];
+    #endif
+
+    return skin;
+}
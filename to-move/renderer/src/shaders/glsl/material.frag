@@ -1,8 +1,69 @@
 #version 300 es
 precision mediump float;
 
+#include "camera"
+
+in vec3 v_position;
+
+#ifdef HAS_NORMALS
+in vec3 v_normal;
+#endif
+
+#ifdef HAS_TANGENTS
+in vec4 v_tangent;
+#endif
+
+#ifdef HAS_UV_0
+in vec2 v_texcoord_0;
+#endif
+
+#ifdef HAS_UV_1
+in vec2 v_texcoord_1;
+#endif
+
+#ifdef HAS_COLOR_0
+in vec4 v_color_0;
+#endif
+
+#include "material"
+#include "pbr"
+#include "lights"
+
 out vec4 final_color;
 
 void main() {
-    final_color = vec4(1.0, 1.0, 1.0, 1.0);
-}
\ No newline at end of file
+    vec4 base_color = get_base_color();
+
+    #ifdef ALPHA_MASK
+    if(base_color.a < u_alpha_cutoff) {
+        discard;
+    }
+    #endif
+
+    vec2 metallic_roughness = get_metallic_roughness();
+    PbrInfo info = get_pbr_info(base_color.rgb, metallic_roughness.x, metallic_roughness.y);
+
+    vec3 normal = get_normal();
+    vec3 camera_position = inverse(u_view)[3].xyz;
+    vec3 view = normalize(camera_position - v_position);
+
+    vec3 color = vec3(0.0);
+
+    for(int i = 0; i < MAX_LIGHTS; i++) {
+        if(i >= int(u_light_count.x)) {
+            break;
+        }
+
+        vec3 to_light;
+        vec3 radiance;
+        get_light(u_lights[i], v_position, to_light, radiance);
+        color += get_brdf(info, normal, view, to_light) * radiance;
+    }
+
+    //TODO - image based lighting, this just keeps the shadows from being pitch black
+    vec3 ambient = vec3(0.03);
+    color += ambient * base_color.rgb * get_occlusion();
+    color += get_emissive();
+
+    final_color = vec4(linear_to_srgb(color), base_color.a);
+}
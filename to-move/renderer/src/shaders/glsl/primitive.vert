@@ -1,17 +1,91 @@
 #version 300 es
 precision mediump float;
 
-layout (std140) uniform camera {
-    uniform mat4 u_view;
-    uniform mat4 u_projection;
-};
+#include "camera"
 
 uniform mat4 u_model;
 
-in vec3 a_position;
+//must match ATTRIBUTE_LOCATIONS in shaders.rs
+
+layout(location = 0) in vec3 a_position;
+out vec3 v_position;
+
+#ifdef HAS_NORMALS
+layout(location = 1) in vec3 a_normal;
+out vec3 v_normal;
+#endif
+
+#ifdef HAS_TANGENTS
+layout(location = 2) in vec4 a_tangent;
+out vec4 v_tangent;
+#endif
+
+#ifdef HAS_UV_0
+layout(location = 3) in vec2 a_texcoord_0;
+out vec2 v_texcoord_0;
+#endif
+
+#ifdef HAS_UV_1
+layout(location = 4) in vec2 a_texcoord_1;
+out vec2 v_texcoord_1;
+#endif
+
+#ifdef HAS_COLOR_0
+//vec3 colors get w=1.0 filled in automatically
+layout(location = 5) in vec4 a_color_0;
+out vec4 v_color_0;
+#endif
+
+//declared for every primitive that has them, but only read with a skin
+#ifdef HAS_JOINTS_0
+layout(location = 6) in vec4 a_joints_0;
+layout(location = 7) in vec4 a_weights_0;
+#endif
+
+#ifdef HAS_JOINTS_1
+layout(location = 8) in vec4 a_joints_1;
+layout(location = 9) in vec4 a_weights_1;
+#endif
+
+#ifdef HAS_SKIN
+#include "skinning"
+#endif
+
+#ifdef MORPH_TARGETS
+//the target attributes aren't bound yet, so the weights aren't applied
+uniform float u_morph_weights[MORPH_TARGETS];
+#endif
 
 void main() {
-    mat4 vp = u_projection * u_view;
-    mat4 mvp = vp * u_model;
-    gl_Position = mvp * vec4(a_position, 1.0); 
-}
\ No newline at end of file
+    mat4 model = u_model;
+
+    #ifdef HAS_SKIN
+    model = model * get_skin_matrix();
+    #endif
+
+    vec4 position = model * vec4(a_position, 1.0);
+    v_position = position.xyz / position.w;
+
+    #ifdef HAS_NORMALS
+    //TODO - use the inverse transpose for non-uniform scale
+    v_normal = normalize(mat3(model) * a_normal);
+    #endif
+
+    #ifdef HAS_TANGENTS
+    v_tangent = vec4(normalize(mat3(model) * a_tangent.xyz), a_tangent.w);
+    #endif
+
+    #ifdef HAS_UV_0
+    v_texcoord_0 = a_texcoord_0;
+    #endif
+
+    #ifdef HAS_UV_1
+    v_texcoord_1 = a_texcoord_1;
+    #endif
+
+    #ifdef HAS_COLOR_0
+    v_color_0 = a_color_0;
+    #endif
+
+    gl_Position = u_projection * u_view * position; 
+}
@@ -1,3 +1,5 @@
 mod shaders;
+mod preprocessor;
 
-pub use self::shaders::*;
\ No newline at end of file
+pub use self::shaders::*;
+pub use self::preprocessor::*;
@@ -0,0 +1,427 @@
+use crate::errors::{Error, NativeError};
+use std::collections::HashMap;
+
+/// The chunks that ship with the renderer
+/// Available to #include from any shader
+const BUILTIN_CHUNKS:&[(&str, &str)] = &[
+    ("camera", include_str!("glsl/chunks/camera.glsl")),
+    ("skinning", include_str!("glsl/chunks/skinning.glsl")),
+    ("material", include_str!("glsl/chunks/material.glsl")),
+    ("pbr", include_str!("glsl/chunks/pbr.glsl")),
+    ("lights", include_str!("glsl/chunks/lights.glsl")),
+];
+
+/// Sources that can be pulled in via #include "name"
+/// Registered chunks take precedence over the built-in ones with the same name
+#[derive(Clone, Debug, Default)]
+pub struct ShaderChunks {
+    registered: HashMap<String, String>,
+}
+
+impl ShaderChunks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name:&str, source:&str) {
+        self.registered.insert(name.to_string(), source.to_string());
+    }
+
+    pub fn unregister(&mut self, name:&str) {
+        self.registered.remove(name);
+    }
+
+    pub fn get(&self, name:&str) -> Option<&str> {
+        self.registered
+            .get(name)
+            .map(|source| source.as_str())
+            .or_else(|| {
+                BUILTIN_CHUNKS
+                    .iter()
+                    .find(|(builtin_name, _)| *builtin_name == name)
+                    .map(|(_, source)| *source)
+            })
+    }
+}
+
+/// Every top-level shader, chunk and define block gets its own source string number
+/// via #line directives, so the driver reports errors against the original file and line
+/// The same map can be shared across the vertex and fragment shader
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    names: Vec<String>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(&self, source_number:usize) -> Option<&str> {
+        self.names.get(source_number).map(|name| name.as_str())
+    }
+
+    fn add(&mut self, name:&str) -> usize {
+        self.names.push(name.to_string());
+        self.names.len() - 1
+    }
+
+    /// Replaces the "source:line:" prefixes in a compiler log
+    /// e.g. "ERROR: 3:12: ..." becomes "ERROR: lights:12: ..."
+    pub fn map_error_log(&self, log:&str) -> String {
+        log
+            .lines()
+            .map(|line| self.map_error_line(line))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn map_error_line(&self, line:&str) -> String {
+        for prefix in &["ERROR: ", "WARNING: "] {
+            if let Some(start) = line.find(prefix) {
+                let location_start = start + prefix.len();
+                let rest = &line[location_start..];
+                let mut parts = rest.splitn(3, ':');
+
+                if let (Some(source_number), Some(line_number), Some(message)) = (parts.next(), parts.next(), parts.next()) {
+                    let name = source_number
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|source_number| self.name(source_number));
+
+                    if let Some(name) = name {
+                        return format!("{}{}:{}:{}", &line[..location_start], name, line_number, message);
+                    }
+                }
+            }
+        }
+
+        line.to_string()
+    }
+}
+
+/// Resolves the #include directives and adds the defines right after #version
+///
+/// * #include "name" (or <name>) is replaced by the chunk, recursively
+/// * including a chunk that's already being included is an error
+/// * #version in an included chunk is dropped, so chunks can still be valid standalone files
+/// * #include inside a /* */ block comment is left alone, like any other directive would be
+///   (a // comment doesn't need anything special, since the line then doesn't start with #include)
+///
+/// The #version line is hoisted to the top even if there are lines (comments, blank lines) before it,
+/// those still get a #line directive so they keep their original line numbers
+pub fn preprocess(name:&str, source:&str, defines:&[String], chunks:&ShaderChunks, source_map:&mut SourceMap) -> Result<String, Error> {
+    let mut output:Vec<String> = Vec::new();
+    let source_number = source_map.add(name);
+
+    let lines:Vec<&str> = source.lines().collect();
+
+    //#version has to be the very first thing in the output
+    let version_index = lines.iter().position(|line| line.trim_start().starts_with("#version"));
+    if let Some(version_index) = version_index {
+        output.push(lines[version_index].to_string());
+    }
+
+    if !defines.is_empty() {
+        let defines_number = source_map.add(&format!("{} (defines)", name));
+        output.push(format!("#line 1 {}", defines_number));
+        output.extend(defines.iter().cloned());
+    }
+
+    let mut stack = vec![name.to_string()];
+    let mut in_comment = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        if Some(index) == version_index {
+            continue;
+        }
+        if index == 0 || Some(index - 1) == version_index {
+            output.push(format!("#line {} {}", index + 1, source_number));
+        }
+
+        if in_comment {
+            output.push(line.to_string());
+        } else {
+            push_line(line, index, source_number, chunks, source_map, &mut stack, &mut output)?;
+        }
+        in_comment = ends_in_comment(line, in_comment);
+    }
+
+    Ok(output.join("\n"))
+}
+
+fn push_chunk(name:&str, chunks:&ShaderChunks, source_map:&mut SourceMap, stack:&mut Vec<String>, output:&mut Vec<String>) -> Result<(), Error> {
+    if stack.iter().any(|other| other == name) {
+        let mut chain = stack.clone();
+        chain.push(name.to_string());
+        return Err(NativeError::ShaderIncludeCycle(chain.join(" -> ")).into());
+    }
+
+    let source = chunks.get(name).ok_or_else(|| Error::from(NativeError::ShaderIncludeMissing(name.to_string())))?;
+    let source_number = source_map.add(name);
+
+    stack.push(name.to_string());
+    output.push(format!("#line 1 {}", source_number));
+
+    let mut in_comment = false;
+
+    for (index, line) in source.lines().enumerate() {
+        if in_comment {
+            output.push(line.to_string());
+        } else if line.trim_start().starts_with("#version") {
+            //keep the line numbers in sync
+            output.push(String::new());
+        } else {
+            push_line(line, index, source_number, chunks, source_map, stack, output)?;
+        }
+        in_comment = ends_in_comment(line, in_comment);
+    }
+
+    stack.pop();
+
+    Ok(())
+}
+
+fn push_line(line:&str, index:usize, source_number:usize, chunks:&ShaderChunks, source_map:&mut SourceMap, stack:&mut Vec<String>, output:&mut Vec<String>) -> Result<(), Error> {
+    match get_include_name(line)? {
+        Some(include_name) => {
+            push_chunk(include_name, chunks, source_map, stack, output)?;
+            //back to where we were in the including file
+            output.push(format!("#line {} {}", index + 2, source_number));
+        },
+        None => {
+            output.push(line.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a /* */ comment is still open at the end of the line
+/// GLSL has no string literals, so there's nothing else that could hide the markers
+fn ends_in_comment(line:&str, mut in_comment:bool) -> bool {
+    let mut rest = line;
+
+    loop {
+        if in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    in_comment = false;
+                    rest = &rest[end+2..];
+                },
+                None => return true
+            }
+        } else {
+            let block = rest.find("/*");
+            let line_comment = rest.find("//");
+            match (block, line_comment) {
+                (Some(start), Some(line_start)) if line_start < start => return false,
+                (Some(start), _) => {
+                    in_comment = true;
+                    rest = &rest[start+2..];
+                },
+                (None, _) => return false
+            }
+        }
+    }
+}
+
+fn get_include_name(line:&str) -> Result<Option<&str>, Error> {
+    let line = line.trim();
+
+    if !line.starts_with("#include") {
+        return Ok(None);
+    }
+
+    let arg = line["#include".len()..].trim();
+
+    let name =
+        if arg.len() >= 2 && ((arg.starts_with('"') && arg.ends_with('"')) || (arg.starts_with('<') && arg.ends_with('>'))) {
+            &arg[1..arg.len()-1]
+        } else {
+            ""
+        };
+
+    if name.is_empty() {
+        Err(NativeError::ShaderInclude(line.to_string()).into())
+    } else {
+        Ok(Some(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_chunks(sources:&[(&str, &str)]) -> ShaderChunks {
+        let mut chunks = ShaderChunks::new();
+        for (name, source) in sources {
+            chunks.register(name, source);
+        }
+        chunks
+    }
+
+    fn run(source:&str, defines:&[&str], chunks:&ShaderChunks) -> Result<(String, SourceMap), Error> {
+        let defines:Vec<String> = defines.iter().map(|define| define.to_string()).collect();
+        let mut source_map = SourceMap::new();
+        let output = preprocess("main", source, &defines, chunks, &mut source_map)?;
+        Ok((output, source_map))
+    }
+
+    fn get_lines(output:&str) -> Vec<&str> {
+        output.lines().collect()
+    }
+
+    #[test]
+    fn nested_include() {
+        let chunks = get_chunks(&[
+            ("outer", "float outer;\n#include \"inner\"\nfloat after_inner;"),
+            ("inner", "float inner;"),
+        ]);
+
+        let (output, source_map) = run("#include \"outer\"\nvoid main() {}", &[], &chunks).unwrap();
+
+        assert_eq!(get_lines(&output), vec![
+            "#line 1 0",
+            "#line 1 1",
+            "float outer;",
+            "#line 1 2",
+            "float inner;",
+            "#line 3 1",
+            "float after_inner;",
+            "#line 2 0",
+            "void main() {}",
+        ]);
+        assert_eq!(source_map.name(1), Some("outer"));
+        assert_eq!(source_map.name(2), Some("inner"));
+    }
+
+    #[test]
+    fn registered_chunk_overrides_builtin() {
+        assert!(ShaderChunks::new().get("camera").unwrap().contains("u_view"));
+
+        let mut chunks = get_chunks(&[("camera", "uniform mat4 u_custom_camera;")]);
+        let (output, _) = run("#include \"camera\"", &[], &chunks).unwrap();
+        assert!(output.contains("u_custom_camera"));
+        assert!(!output.contains("u_view"));
+
+        chunks.unregister("camera");
+        let (output, _) = run("#include \"camera\"", &[], &chunks).unwrap();
+        assert!(output.contains("u_view"));
+    }
+
+    #[test]
+    fn include_cycle() {
+        let chunks = get_chunks(&[
+            ("a", "#include \"b\""),
+            ("b", "#include <a>"),
+        ]);
+
+        match run("#include \"a\"", &[], &chunks) {
+            Err(Error::Native(NativeError::ShaderIncludeCycle(chain))) => assert_eq!(chain, "main -> a -> b -> a"),
+            other => panic!("expected a cycle, got {:?}", other.map(|(output, _)| output)),
+        }
+    }
+
+    #[test]
+    fn same_chunk_twice_isnt_a_cycle() {
+        let chunks = get_chunks(&[("a", "float a;")]);
+        let (output, _) = run("#include \"a\"\n#include \"a\"", &[], &chunks).unwrap();
+        assert_eq!(output.matches("float a;").count(), 2);
+    }
+
+    #[test]
+    fn missing_chunk() {
+        let result = run("#include \"nope\"", &[], &ShaderChunks::new());
+        assert!(matches!(result, Err(Error::Native(NativeError::ShaderIncludeMissing(ref name))) if name == "nope"));
+    }
+
+    #[test]
+    fn malformed_include() {
+        for line in &["#include", "#include nope", "#include \"nope", "#include \"\"", "#include <nope\""] {
+            let result = run(line, &[], &ShaderChunks::new());
+            assert!(matches!(result, Err(Error::Native(NativeError::ShaderInclude(_)))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn version_stays_first() {
+        let (output, source_map) = run("#version 300 es\nprecision highp float;", &["#define A", "#define B"], &ShaderChunks::new()).unwrap();
+
+        assert_eq!(get_lines(&output), vec![
+            "#version 300 es",
+            "#line 1 1",
+            "#define A",
+            "#define B",
+            "#line 2 0",
+            "precision highp float;",
+        ]);
+        assert_eq!(source_map.name(1), Some("main (defines)"));
+    }
+
+    #[test]
+    fn lines_before_version_keep_their_numbers() {
+        let (output, _) = run("// a comment\n\n#version 300 es\nprecision highp float;", &["#define A"], &ShaderChunks::new()).unwrap();
+
+        assert_eq!(get_lines(&output), vec![
+            "#version 300 es",
+            "#line 1 1",
+            "#define A",
+            "#line 1 0",
+            "// a comment",
+            "",
+            "#line 4 0",
+            "precision highp float;",
+        ]);
+    }
+
+    #[test]
+    fn version_in_chunk_is_dropped() {
+        let chunks = get_chunks(&[("a", "#version 300 es\nfloat a;")]);
+        let (output, _) = run("#version 300 es\n#include \"a\"", &[], &chunks).unwrap();
+
+        assert_eq!(output.matches("#version").count(), 1);
+        assert!(get_lines(&output).contains(&"float a;"));
+    }
+
+    #[test]
+    fn include_in_comment_is_ignored() {
+        let source = "// #include \"nope\"\n/* start\n#include \"nope\"\n*/\n#include \"a\"\n/* one line */ float b;";
+        let chunks = get_chunks(&[("a", "float a;")]);
+        let (output, _) = run(source, &[], &chunks).unwrap();
+
+        assert!(output.contains("float a;"));
+        assert_eq!(output.matches("#include \"nope\"").count(), 2);
+    }
+
+    #[test]
+    fn comment_state() {
+        assert!(ends_in_comment("/* open", false));
+        assert!(!ends_in_comment("/* closed */", false));
+        assert!(ends_in_comment("still open", true));
+        assert!(!ends_in_comment("closes */ float a;", true));
+        assert!(ends_in_comment("/* a */ /* b", false));
+        assert!(!ends_in_comment("float a; // /* not a block", false));
+    }
+
+    #[test]
+    fn map_error_log() {
+        let chunks = get_chunks(&[("lights", "float a;\nfloat b;")]);
+        let defines = ["#define A".to_string()];
+        let mut source_map = SourceMap::new();
+        preprocess("vertex", "#include \"lights\"", &defines, &chunks, &mut source_map).unwrap();
+        preprocess("fragment", "#include \"lights\"", &[], &chunks, &mut source_map).unwrap();
+
+        let log = "ERROR: 3:12: 'x' : undeclared identifier\nWARNING: 0:1: something\nERROR: 9:1: unknown source\nnot an error";
+        assert_eq!(source_map.map_error_log(log), [
+            "ERROR: fragment:12: 'x' : undeclared identifier",
+            "WARNING: vertex:1: something",
+            "ERROR: 9:1: unknown source",
+            "not an error",
+        ].join("\n"));
+        assert_eq!(source_map.name(1), Some("vertex (defines)"));
+        assert_eq!(source_map.name(2), Some("lights"));
+        assert_eq!(source_map.name(4), Some("lights"));
+    }
+}
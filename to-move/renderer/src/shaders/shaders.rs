@@ -1,32 +1,456 @@
 use awsm_web::webgl::{WebGl2Renderer, Id};
-use crate::errors::{Error};
+use crate::errors::{Error, NativeError};
+use super::preprocessor::{preprocess, ShaderChunks, SourceMap};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
+/// Everything that changes the compiled program
+/// Primitives with equal settings share the same program
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
 pub struct ShaderSettings{
-    pub has_position: bool
+    //attributes
+    pub has_position: bool,
+    pub has_normal: bool,
+    pub has_tangent: bool,
+    //number of sets, e.g. n_texcoords of 2 means TEXCOORD_0 and TEXCOORD_1
+    pub n_texcoords: u32,
+    pub n_colors: u32,
+    //sets of JOINTS_n + WEIGHTS_n (4 influences each)
+    pub n_joint_sets: u32,
+
+    //skinning and morphing
+    /// Size of the joint matrix array
+    /// 0 until there are joint matrices to upload, the joint attributes are declared but unused until then
+    pub max_joints: u32,
+    /// Declares the morph weights, the targets themselves aren't bound yet
+    pub n_morph_targets: u32,
+
+    //material
+    pub has_base_color_texture: bool,
+    pub has_metallic_roughness_texture: bool,
+    pub has_normal_texture: bool,
+    pub has_occlusion_texture: bool,
+    pub has_emissive_texture: bool,
+    pub alpha_mask: bool,
 }
 
 impl ShaderSettings {
-    pub fn get_hash(&self) -> u32 {
-        //TODO - implement hasher or derive
-        42
+    pub fn get_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The #defines for this permutation
+    pub fn get_defines(&self) -> Vec<String> {
+        let mut defines = Vec::new();
+
+        let mut flag = |enabled:bool, name:&str| {
+            if enabled {
+                defines.push(format!("#define {}", name));
+            }
+        };
+
+        flag(self.has_normal, "HAS_NORMALS");
+        flag(self.has_tangent, "HAS_TANGENTS");
+
+        //one per set, only the ones with an attribute location are ever set
+        for set in 0..self.n_texcoords {
+            flag(true, &format!("HAS_UV_{}", set));
+        }
+        for set in 0..self.n_colors {
+            flag(true, &format!("HAS_COLOR_{}", set));
+        }
+        for set in 0..self.n_joint_sets {
+            flag(true, &format!("HAS_JOINTS_{}", set));
+        }
+
+        flag(self.has_base_color_texture, "HAS_BASE_COLOR_MAP");
+        flag(self.has_metallic_roughness_texture, "HAS_METALLIC_ROUGHNESS_MAP");
+        flag(self.has_normal_texture, "HAS_NORMAL_MAP");
+        flag(self.has_occlusion_texture, "HAS_OCCLUSION_MAP");
+        flag(self.has_emissive_texture, "HAS_EMISSIVE_MAP");
+        flag(self.alpha_mask, "ALPHA_MASK");
+
+        if self.max_joints > 0 && self.n_joint_sets > 0 {
+            defines.push("#define HAS_SKIN".to_string());
+            defines.push(format!("#define MAX_JOINTS {}", self.max_joints));
+        }
+        if self.n_morph_targets > 0 {
+            defines.push(format!("#define MORPH_TARGETS {}", self.n_morph_targets));
+        }
+
+        defines
+    }
+
+    /// The vertex and fragment source for this permutation, with includes resolved
+    /// The source map is shared by both, for mapping compile errors back to their chunks
+    pub fn get_sources(&self, chunks:&ShaderChunks) -> Result<(String, String, SourceMap), Error> {
+        let defines = self.get_defines();
+        let mut source_map = SourceMap::new();
+
+        let vertex = preprocess("primitive.vert", PRIMITIVE_VERT, &defines, chunks, &mut source_map)?;
+        let fragment = preprocess("material.frag", MATERIAL_FRAG, &defines, chunks, &mut source_map)?;
+
+        Ok((vertex, fragment, source_map))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ShaderCacheStats {
+    /// number of programs actually compiled
+    pub compiled: usize,
+    /// number of times an existing program was reused
+    pub hits: usize,
+}
+
+/// Compiled programs keyed by their settings
+#[derive(Default)]
+pub struct ShaderCache {
+    programs: HashMap<ShaderSettings, Id>,
+    stats: ShaderCacheStats,
+    chunks: ShaderChunks,
+}
+
+impl ShaderCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> &ShaderCacheStats {
+        &self.stats
     }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    pub fn chunks(&self) -> &ShaderChunks {
+        &self.chunks
+    }
+
+    /// Programs that were already compiled aren't rebuilt
+    /// so register chunks before uploading anything that uses them
+    pub fn chunks_mut(&mut self) -> &mut ShaderChunks {
+        &mut self.chunks
+    }
+
+    /// Returns the cached program for these settings
+    /// compile is only called (with the settings' sources) if there isn't one yet
+    pub fn get_or_compile(&mut self, shader_settings:&ShaderSettings, compile: impl FnOnce(&str, &str, &SourceMap) -> Result<Id, Error>) -> Result<Id, Error> {
+        if let Some(program_id) = self.programs.get(shader_settings) {
+            self.stats.hits += 1;
+            return Ok(*program_id);
+        }
+
+        let (vertex_shader, fragment_shader, source_map) = shader_settings.get_sources(&self.chunks)?;
+        let program_id = compile(&vertex_shader, &fragment_shader, &source_map)?;
+
+        self.programs.insert(shader_settings.clone(), program_id);
+        self.stats.compiled += 1;
+
+        Ok(program_id)
+    }
+}
+
+/// Every vertex array binds its attributes to these locations, whatever program it's drawn with
+/// The built-in shader declares them with layout(location = N)
+/// and custom material shaders have to do the same for the attributes they read
+pub const ATTRIBUTE_LOCATIONS:&[(&str, u32)] = &[
+    ("a_position", 0),
+    ("a_normal", 1),
+    ("a_tangent", 2),
+    ("a_texcoord_0", 3),
+    ("a_texcoord_1", 4),
+    ("a_color_0", 5),
+    ("a_joints_0", 6),
+    ("a_weights_0", 7),
+    ("a_joints_1", 8),
+    ("a_weights_1", 9),
+];
+
+/// None for attributes that no shader reads (e.g. a third uv set), which aren't bound at all
+pub fn get_attribute_location(attribute_name:&str) -> Option<u32> {
+    ATTRIBUTE_LOCATIONS
+        .iter()
+        .find(|(name, _)| *name == attribute_name)
+        .map(|(_, location)| *location)
 }
 
 const PRIMITIVE_VERT:&str = include_str!("glsl/primitive.vert");
 
 const MATERIAL_FRAG:&str = include_str!("glsl/material.frag");
 
-pub fn compile_shader(webgl:&mut WebGl2Renderer) -> Result<Id, Error> {
-
-    let shader_settings = ShaderSettings {
-        has_position: true
-    };
-    //TODO - look it up in hash map and return early if found 
-    let _shader_hash = shader_settings.get_hash();
+/// Returns the cached program for these settings, compiling it first if needed
+/// Either way the program is left active (e.g. for assigning vertex arrays against its attribute locations)
+pub fn compile_shader(webgl:&mut WebGl2Renderer, cache:&mut ShaderCache, shader_settings:&ShaderSettings) -> Result<Id, Error> {
+    let program_id = cache.get_or_compile(shader_settings, |vertex_shader, fragment_shader, source_map| {
+        webgl
+            .compile_program(vertex_shader, fragment_shader)
+            .map_err(|err| NativeError::ShaderCompile(source_map.map_error_log(&Error::from(err).to_string())).into())
+    })?;
 
-    let vertex_shader = PRIMITIVE_VERT;
-    let fragment_shader = MATERIAL_FRAG;
-    let program_id = webgl.compile_program(&vertex_shader, &fragment_shader)?;
+    //compiling activates it, but a cache hit doesn't
+    webgl.activate_program(program_id)?;
 
     Ok(program_id)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn get_full_settings() -> ShaderSettings {
+        ShaderSettings {
+            has_position: true,
+            has_normal: true,
+            has_tangent: true,
+            n_texcoords: 2,
+            n_colors: 1,
+            n_joint_sets: 2,
+            max_joints: 64,
+            n_morph_targets: 2,
+            has_base_color_texture: true,
+            has_metallic_roughness_texture: true,
+            has_normal_texture: true,
+            has_occlusion_texture: true,
+            has_emissive_texture: true,
+            alpha_mask: true,
+        }
+    }
+
+    //every combination of the flags, with up to 2 uv sets and 1 color set
+    //and joints without a skin, with a skin (1 or 2 sets) or morph targets
+    fn get_permutations() -> Vec<ShaderSettings> {
+        let mut permutations = Vec::new();
+        let skins = [(0, 0, 0), (1, 0, 0), (1, 32, 0), (2, 64, 0), (0, 0, 4)];
+
+        for bits in 0..(1u32 << 8) {
+            for n_texcoords in 0..=2 {
+                for n_colors in 0..=1 {
+                    for (n_joint_sets, max_joints, n_morph_targets) in skins.iter().copied() {
+                        let flag = |bit:u32| bits & (1 << bit) != 0;
+                        permutations.push(ShaderSettings {
+                            has_position: true,
+                            has_normal: flag(0),
+                            has_tangent: flag(1),
+                            n_texcoords,
+                            n_colors,
+                            n_joint_sets,
+                            max_joints,
+                            n_morph_targets,
+                            has_base_color_texture: flag(2),
+                            has_metallic_roughness_texture: flag(3),
+                            has_normal_texture: flag(4),
+                            has_occlusion_texture: flag(5),
+                            has_emissive_texture: flag(6),
+                            alpha_mask: flag(7),
+                        });
+                    }
+                }
+            }
+        }
+
+        permutations
+    }
+
+    fn get_define_name(define:&str) -> &str {
+        define.trim_start_matches("#define ").split(' ').next().unwrap()
+    }
+
+    #[test]
+    fn no_defines_by_default() {
+        let settings = ShaderSettings::default();
+        assert!(settings.get_defines().is_empty());
+
+        let (vertex, fragment, _) = settings.get_sources(&ShaderChunks::new()).unwrap();
+        for source in &[vertex, fragment] {
+            assert!(source.starts_with("#version 300 es\n"));
+            assert!(!source.contains("#define HAS_"));
+        }
+    }
+
+    #[test]
+    fn defines_per_setting() {
+        let check = |f: &dyn Fn(&mut ShaderSettings), expected:&[&str]| {
+            let mut settings = ShaderSettings::default();
+            f(&mut settings);
+            let defines:Vec<String> = expected.iter().map(|name| format!("#define {}", name)).collect();
+            assert_eq!(settings.get_defines(), defines);
+        };
+
+        check(&|s| s.has_normal = true, &["HAS_NORMALS"]);
+        check(&|s| s.has_tangent = true, &["HAS_TANGENTS"]);
+        check(&|s| s.n_texcoords = 2, &["HAS_UV_0", "HAS_UV_1"]);
+        check(&|s| s.n_colors = 1, &["HAS_COLOR_0"]);
+        check(&|s| s.has_base_color_texture = true, &["HAS_BASE_COLOR_MAP"]);
+        check(&|s| s.has_metallic_roughness_texture = true, &["HAS_METALLIC_ROUGHNESS_MAP"]);
+        check(&|s| s.has_normal_texture = true, &["HAS_NORMAL_MAP"]);
+        check(&|s| s.has_occlusion_texture = true, &["HAS_OCCLUSION_MAP"]);
+        check(&|s| s.has_emissive_texture = true, &["HAS_EMISSIVE_MAP"]);
+        check(&|s| s.alpha_mask = true, &["ALPHA_MASK"]);
+        check(&|s| s.n_joint_sets = 2, &["HAS_JOINTS_0", "HAS_JOINTS_1"]);
+        check(&|s| s.n_morph_targets = 3, &["MORPH_TARGETS 3"]);
+
+        //skinning needs joints, and joint matrices to upload
+        check(&|s| s.max_joints = 32, &[]);
+        check(&|s| { s.n_joint_sets = 1; s.max_joints = 32; }, &["HAS_JOINTS_0", "HAS_SKIN", "MAX_JOINTS 32"]);
+    }
+
+    #[test]
+    fn skin_is_part_of_the_key() {
+        let mut settings = ShaderSettings { n_joint_sets: 1, ..ShaderSettings::default() };
+        let unskinned = settings.get_hash();
+        settings.max_joints = 32;
+        let skinned = settings.get_hash();
+        settings.max_joints = 64;
+
+        assert_ne!(unskinned, skinned);
+        assert_ne!(skinned, settings.get_hash());
+
+        let (vertex, _, _) = settings.get_sources(&ShaderChunks::new()).unwrap();
+        assert!(vertex.contains("uniform mat4 u_joint_matrices[MAX_JOINTS];"));
+        assert!(vertex.contains("model = model * get_skin_matrix();"));
+    }
+
+    #[test]
+    fn every_define_is_used() {
+        let settings = get_full_settings();
+        let (vertex, fragment, _) = settings.get_sources(&ShaderChunks::new()).unwrap();
+
+        for define in settings.get_defines() {
+            let name = get_define_name(&define);
+            //either a flag that's checked, or a value that's used
+            let used = |source:&str| source.contains(&format!("#ifdef {}", name)) || source.contains(&format!("[{}]", name));
+            assert!(used(&vertex) || used(&fragment), "{} isn't used by any shader", name);
+        }
+    }
+
+    #[test]
+    fn sources_for_each_permutation() {
+        let chunks = ShaderChunks::new();
+        let permutations = get_permutations();
+        let mut hashes = HashSet::new();
+
+        for settings in permutations.iter() {
+            let defines = settings.get_defines();
+            let (vertex, fragment, _) = settings.get_sources(&chunks).unwrap();
+
+            for source in &[&vertex, &fragment] {
+                let lines:Vec<&str> = source.lines().collect();
+                assert_eq!(lines[0], "#version 300 es");
+
+                //defines come right after #version, and nowhere else
+                //(chunks have their own constants, e.g. MAX_LIGHTS)
+                let is_setting = |line:&&str| {
+                    ["#define HAS_", "#define ALPHA_MASK", "#define MAX_JOINTS ", "#define MORPH_TARGETS "].iter().any(|prefix| line.starts_with(prefix))
+                };
+                let source_defines:Vec<&str> = lines.iter().copied().filter(is_setting).collect();
+                assert_eq!(source_defines, defines);
+                if !defines.is_empty() {
+                    assert!(lines[1].starts_with("#line 1 "));
+                    assert_eq!(&lines[2..2 + defines.len()], defines.as_slice());
+                }
+
+                //includes are resolved
+                assert!(!lines.iter().any(|line| line.trim_start().starts_with("#include")));
+                assert_eq!(lines.iter().filter(|line| line.starts_with("#version")).count(), 1);
+            }
+
+            hashes.insert(settings.get_hash());
+        }
+
+        //no two permutations share a program
+        assert_eq!(hashes.len(), permutations.len());
+    }
+
+    #[test]
+    fn attributes_are_declared_at_their_location() {
+        let settings = get_full_settings();
+        let (vertex, _, _) = settings.get_sources(&ShaderChunks::new()).unwrap();
+
+        let declared:Vec<(String, u32)> = vertex
+            .lines()
+            .filter(|line| line.starts_with("layout(location = "))
+            .map(|line| {
+                let location = line["layout(location = ".len()..].split(')').next().unwrap().parse().unwrap();
+                let name = line.trim_end_matches(';').rsplit(' ').next().unwrap().to_string();
+                (name, location)
+            })
+            .collect();
+
+        assert_eq!(declared.len(), ATTRIBUTE_LOCATIONS.len());
+        for (name, location) in declared {
+            assert_eq!(get_attribute_location(&name), Some(location), "{}", name);
+        }
+
+        //nothing is declared without a location
+        assert!(!vertex.lines().any(|line| line.starts_with("in ")));
+    }
+
+    #[test]
+    fn locations_are_unique() {
+        let locations:HashSet<u32> = ATTRIBUTE_LOCATIONS.iter().map(|(_, location)| *location).collect();
+        assert_eq!(locations.len(), ATTRIBUTE_LOCATIONS.len());
+        assert_eq!(get_attribute_location("a_texcoord_2"), None);
+    }
+
+    fn get_program_id() -> Id {
+        beach_map::BeachMap::<beach_map::DefaultVersion, ()>::default().insert(())
+    }
+
+    #[test]
+    fn identical_settings_share_a_program() {
+        let mut cache = ShaderCache::new();
+        let settings = get_full_settings();
+        let program_id = get_program_id();
+        let mut n_compiled = 0;
+
+        for _ in 0..1000 {
+            let id = cache.get_or_compile(&settings, |_, _, _| {
+                n_compiled += 1;
+                Ok(program_id)
+            }).unwrap();
+            assert_eq!(id, program_id);
+        }
+
+        assert_eq!(n_compiled, 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().compiled, 1);
+        assert_eq!(cache.stats().hits, 999);
+    }
+
+    #[test]
+    fn different_settings_compile_again() {
+        let mut cache = ShaderCache::new();
+        let mut settings = ShaderSettings::default();
+        cache.get_or_compile(&settings, |_, _, _| Ok(get_program_id())).unwrap();
+        settings.has_normal = true;
+        cache.get_or_compile(&settings, |vertex, _, _| {
+            assert!(vertex.contains("#define HAS_NORMALS"));
+            Ok(get_program_id())
+        }).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().compiled, 2);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn failed_compile_isnt_cached() {
+        let mut cache = ShaderCache::new();
+        let settings = ShaderSettings::default();
+        assert!(cache.get_or_compile(&settings, |_, _, _| Err(NativeError::ShaderCompile("".to_string()).into())).is_err());
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().compiled, 0);
+
+        cache.get_or_compile(&settings, |_, _, _| Ok(get_program_id())).unwrap();
+        assert_eq!(cache.stats().compiled, 1);
+    }
+}
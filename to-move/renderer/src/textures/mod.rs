@@ -0,0 +1,3 @@
+mod textures;
+
+pub use self::textures::*;
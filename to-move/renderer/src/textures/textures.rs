@@ -0,0 +1,405 @@
+use awsm_web::webgl::{
+    Id,
+    WebGl2Renderer,
+    TextureTarget,
+    SimpleTextureOptions,
+    TextureMinFilter,
+    TextureMagFilter,
+    TextureWrapMode,
+    PixelFormat,
+    WebGlTextureSource
+};
+use web_sys::{HtmlImageElement, WebGl2RenderingContext};
+use crate::errors::{Error, NativeError};
+use crate::renderer::Renderer;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MinFilter {
+    Nearest,
+    Linear,
+    NearestMipmapNearest,
+    LinearMipmapNearest,
+    NearestMipmapLinear,
+    LinearMipmapLinear,
+}
+
+impl MinFilter {
+    pub fn uses_mipmaps(&self) -> bool {
+        !matches!(self, MinFilter::Nearest | MinFilter::Linear)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MagFilter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    ClampToEdge,
+    MirroredRepeat,
+    Repeat,
+}
+
+/// How the texture is sampled
+/// Mipmaps are generated whenever the min filter uses them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    pub min_filter: MinFilter,
+    pub mag_filter: MagFilter,
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            min_filter: MinFilter::LinearMipmapLinear,
+            mag_filter: MagFilter::Linear,
+            wrap_s: WrapMode::Repeat,
+            wrap_t: WrapMode::Repeat,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum TextureKey {
+    //the image's src
+    Image(String),
+    //given by the caller for raw data
+    Named(String),
+}
+
+/// Keeps track of the textures that have been created
+/// so that the same source with the same sampler is only uploaded once
+///
+/// Every create_* call counts as one use of the returned texture (de-duped or not)
+/// and delete_texture only deletes it once every use has been released
+///
+/// Non-power-of-two images are uploaded with the sampler as given
+/// The constraints on them (CLAMP_TO_EDGE and no mipmap filters, or the texture samples as black)
+/// only exist in WebGL1, and this only ever runs on a WebGl2Renderer,
+/// where they can repeat and have mipmaps like any other size
+#[derive(Default)]
+pub struct TextureManager {
+    ids: HashMap<(TextureKey, SamplerOptions), Id>,
+    //Id isn't Hash
+    ref_counts: Vec<(Id, usize)>,
+}
+
+impl TextureManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of live textures, whether they're de-duped or not
+    pub fn len(&self) -> usize {
+        self.ref_counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ref_counts.is_empty()
+    }
+
+    /// Images are de-duped by their src (e.g. the same url loaded by different resources)
+    /// Images without a src can't be told apart, so each call uploads a new texture
+    /// (reuse the returned Id to share those)
+    pub fn create_image_texture(&mut self, webgl:&mut WebGl2Renderer, image:&HtmlImageElement, sampler:&SamplerOptions) -> Result<Id, Error> {
+        let src = image.src();
+        let key = if src.is_empty() { None } else { Some(TextureKey::Image(src)) };
+
+        self.get_or_create(webgl, key, sampler, &WebGlTextureSource::ImageElement(image))
+    }
+
+    /// Tightly-packed RGBA bytes, row by row
+    /// If there's a key, it's de-duped by that key
+    pub fn create_rgba_texture(&mut self, webgl:&mut WebGl2Renderer, key:Option<&str>, width:u32, height:u32, data:&[u8], sampler:&SamplerOptions) -> Result<Id, Error> {
+        let expected = (width * height * 4) as usize;
+        if data.len() != expected {
+            return Err(NativeError::TextureSize(expected, data.len()).into());
+        }
+
+        let key = key.map(|key| TextureKey::Named(key.to_string()));
+
+        //copied into a js typed array, since the texture upload needs an ArrayBufferView
+        let data = js_sys::Uint8Array::from(data);
+
+        self.get_or_create(webgl, key, sampler, &WebGlTextureSource::ArrayBufferView(&data, width, height, 1))
+    }
+
+    /// Releases one use of the texture
+    /// The GPU texture is only deleted when nothing else got it from create_*
+    /// Returns whether it was actually deleted
+    pub fn delete_texture(&mut self, webgl:&mut WebGl2Renderer, texture_id:Id) -> Result<bool, Error> {
+        if !self.release(texture_id)? {
+            return Ok(false);
+        }
+
+        let texture = webgl.get_texture(texture_id)?;
+        webgl.gl.delete_texture(Some(texture));
+
+        Ok(true)
+    }
+
+    /// Adds a use of a texture that was already created
+    /// e.g. when the same Id is given to another primitive's material
+    pub fn retain_texture(&mut self, texture_id:Id) -> Result<(), Error> {
+        let (_, ref_count) = self.ref_counts.iter_mut().find(|(other, _)| *other == texture_id).ok_or(NativeError::TextureMissing)?;
+        *ref_count += 1;
+        Ok(())
+    }
+
+    //the bookkeeping side of delete_texture, returns whether it's no longer used
+    fn release(&mut self, texture_id:Id) -> Result<bool, Error> {
+        let index = self.ref_counts.iter().position(|(other, _)| *other == texture_id).ok_or(NativeError::TextureMissing)?;
+        let ref_count = &mut self.ref_counts[index].1;
+        *ref_count -= 1;
+
+        if *ref_count > 0 {
+            return Ok(false);
+        }
+
+        self.ref_counts.swap_remove(index);
+        self.ids.retain(|_, other| *other != texture_id);
+
+        Ok(true)
+    }
+
+    fn get_or_create(&mut self, webgl:&mut WebGl2Renderer, key:Option<TextureKey>, sampler:&SamplerOptions, source:&WebGlTextureSource) -> Result<Id, Error> {
+        let key = key.map(|key| (key, *sampler));
+
+        if let Some(texture_id) = self.reuse(key.as_ref())? {
+            return Ok(texture_id);
+        }
+
+        let texture_id = upload_texture(webgl, sampler, source)?;
+        self.insert(key, texture_id);
+
+        Ok(texture_id)
+    }
+
+    //counts another use of the texture that was created for the key, if there is one
+    fn reuse(&mut self, key:Option<&(TextureKey, SamplerOptions)>) -> Result<Option<Id>, Error> {
+        match key.and_then(|key| self.ids.get(key)).copied() {
+            Some(texture_id) => {
+                self.retain_texture(texture_id)?;
+                Ok(Some(texture_id))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn insert(&mut self, key:Option<(TextureKey, SamplerOptions)>, texture_id:Id) {
+        if let Some(key) = key {
+            self.ids.insert(key, texture_id);
+        }
+        self.ref_counts.push((texture_id, 1));
+    }
+}
+
+fn upload_texture(webgl:&mut WebGl2Renderer, sampler:&SamplerOptions, source:&WebGlTextureSource) -> Result<Id, Error> {
+    let texture_id = webgl.create_texture()?;
+
+    webgl.assign_simple_texture(
+        texture_id,
+        TextureTarget::Texture2d,
+        &SimpleTextureOptions {
+            flip_y: Some(false),
+            filter_min: Some(get_webgl_min_filter(sampler.min_filter)),
+            filter_mag: Some(get_webgl_mag_filter(sampler.mag_filter)),
+            wrap_s: Some(get_webgl_wrap_mode(sampler.wrap_s)),
+            wrap_t: Some(get_webgl_wrap_mode(sampler.wrap_t)),
+            pixel_format: PixelFormat::Rgba,
+            ..SimpleTextureOptions::default()
+        },
+        source
+    )?;
+
+    //without them the texture would be incomplete (i.e. sample as black)
+    //assigning the texture left it bound, but bind it explicitly rather than rely on that
+    if sampler.min_filter.uses_mipmaps() {
+        let texture = webgl.get_texture(texture_id)?;
+        webgl.gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(texture));
+        webgl.gl.generate_mipmap(WebGl2RenderingContext::TEXTURE_2D);
+    }
+
+    Ok(texture_id)
+}
+
+fn get_webgl_min_filter(filter:MinFilter) -> TextureMinFilter {
+    match filter {
+        MinFilter::Nearest => TextureMinFilter::Nearest,
+        MinFilter::Linear => TextureMinFilter::Linear,
+        MinFilter::NearestMipmapNearest => TextureMinFilter::NearestMipMapNearest,
+        MinFilter::LinearMipmapNearest => TextureMinFilter::LinearMipMapNearest,
+        MinFilter::NearestMipmapLinear => TextureMinFilter::NearestMipMapLinear,
+        MinFilter::LinearMipmapLinear => TextureMinFilter::LinearMipMapLinear,
+    }
+}
+
+fn get_webgl_mag_filter(filter:MagFilter) -> TextureMagFilter {
+    match filter {
+        MagFilter::Nearest => TextureMagFilter::Nearest,
+        MagFilter::Linear => TextureMagFilter::Linear,
+    }
+}
+
+fn get_webgl_wrap_mode(mode:WrapMode) -> TextureWrapMode {
+    match mode {
+        WrapMode::ClampToEdge => TextureWrapMode::ClampToEdge,
+        WrapMode::MirroredRepeat => TextureWrapMode::MirroredRepeat,
+        WrapMode::Repeat => TextureWrapMode::Repeat,
+    }
+}
+
+impl Renderer {
+    pub fn create_image_texture(&mut self, image:&HtmlImageElement, sampler:&SamplerOptions) -> Result<Id, Error> {
+        let mut webgl = self.webgl.borrow_mut();
+        self.textures.create_image_texture(&mut webgl, image, sampler)
+    }
+
+    pub fn create_rgba_texture(&mut self, key:Option<&str>, width:u32, height:u32, data:&[u8], sampler:&SamplerOptions) -> Result<Id, Error> {
+        let mut webgl = self.webgl.borrow_mut();
+        self.textures.create_rgba_texture(&mut webgl, key, width, height, data, sampler)
+    }
+
+    /// See TextureManager::delete_texture
+    pub fn delete_texture(&mut self, texture_id:Id) -> Result<bool, Error> {
+        let mut webgl = self.webgl.borrow_mut();
+        self.textures.delete_texture(&mut webgl, texture_id)
+    }
+
+    /// See TextureManager::retain_texture
+    pub fn retain_texture(&mut self, texture_id:Id) -> Result<(), Error> {
+        self.textures.retain_texture(texture_id)
+    }
+
+    /// Releases one use of each (the same texture can be listed more than once)
+    pub(crate) fn release_textures(&mut self, texture_ids:Vec<Id>) -> Result<(), Error> {
+        let mut webgl = self.webgl.borrow_mut();
+
+        for texture_id in texture_ids {
+            self.textures.delete_texture(&mut webgl, texture_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_ids(count:usize) -> Vec<Id> {
+        let mut ids = beach_map::BeachMap::<beach_map::DefaultVersion, ()>::default();
+        (0..count).map(|_| ids.insert(())).collect()
+    }
+
+    fn image_key(src:&str, sampler:SamplerOptions) -> Option<(TextureKey, SamplerOptions)> {
+        Some((TextureKey::Image(src.to_string()), sampler))
+    }
+
+    //what get_or_create does, with the upload replaced by the given id
+    fn create(textures:&mut TextureManager, key:Option<(TextureKey, SamplerOptions)>, new_id:Id) -> Id {
+        match textures.reuse(key.as_ref()).unwrap() {
+            Some(texture_id) => texture_id,
+            None => {
+                textures.insert(key, new_id);
+                new_id
+            }
+        }
+    }
+
+    fn is_missing(result:Result<bool, Error>) -> bool {
+        matches!(result, Err(Error::Native(NativeError::TextureMissing)))
+    }
+
+    #[test]
+    fn same_src_and_sampler_is_shared() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(2);
+
+        let first = create(&mut textures, image_key("duck.png", SamplerOptions::default()), ids[0]);
+        let second = create(&mut textures, image_key("duck.png", SamplerOptions::default()), ids[1]);
+
+        assert_eq!(first, ids[0]);
+        assert_eq!(second, ids[0]);
+        assert_eq!(textures.len(), 1);
+    }
+
+    #[test]
+    fn different_sampler_is_distinct() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(3);
+        let clamped = SamplerOptions { wrap_s: WrapMode::ClampToEdge, ..SamplerOptions::default() };
+
+        let repeat = create(&mut textures, image_key("duck.png", SamplerOptions::default()), ids[0]);
+        let clamp = create(&mut textures, image_key("duck.png", clamped), ids[1]);
+        let other = create(&mut textures, image_key("other.png", SamplerOptions::default()), ids[2]);
+
+        assert_eq!((repeat, clamp, other), (ids[0], ids[1], ids[2]));
+        assert_eq!(textures.len(), 3);
+    }
+
+    #[test]
+    fn image_and_named_keys_dont_mix() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(2);
+
+        let image = create(&mut textures, image_key("duck", SamplerOptions::default()), ids[0]);
+        let named = create(&mut textures, Some((TextureKey::Named("duck".to_string()), SamplerOptions::default())), ids[1]);
+
+        assert_ne!(image, named);
+    }
+
+    #[test]
+    fn unkeyed_is_never_shared() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(2);
+
+        assert_eq!(create(&mut textures, None, ids[0]), ids[0]);
+        assert_eq!(create(&mut textures, None, ids[1]), ids[1]);
+        assert_eq!(textures.len(), 2);
+    }
+
+    #[test]
+    fn only_freed_at_zero() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(2);
+        let key = image_key("duck.png", SamplerOptions::default());
+
+        let texture_id = create(&mut textures, key.clone(), ids[0]);
+        create(&mut textures, key.clone(), ids[1]);
+        textures.retain_texture(texture_id).unwrap();
+
+        //three uses
+        assert!(!textures.release(texture_id).unwrap());
+        assert!(!textures.release(texture_id).unwrap());
+        assert_eq!(textures.len(), 1);
+        assert!(textures.release(texture_id).unwrap());
+        assert!(textures.is_empty());
+
+        //and once it's gone, the key creates a new one
+        assert!(is_missing(textures.release(texture_id)));
+        assert_eq!(create(&mut textures, key, ids[1]), ids[1]);
+    }
+
+    #[test]
+    fn unknown_texture() {
+        let mut textures = TextureManager::new();
+        let ids = get_ids(1);
+
+        assert!(is_missing(textures.release(ids[0])));
+        assert!(matches!(textures.retain_texture(ids[0]), Err(Error::Native(NativeError::TextureMissing))));
+    }
+
+    #[test]
+    fn mipmap_filters() {
+        assert!(!MinFilter::Nearest.uses_mipmaps());
+        assert!(!MinFilter::Linear.uses_mipmaps());
+        assert!(MinFilter::NearestMipmapNearest.uses_mipmaps());
+        assert!(MinFilter::LinearMipmapLinear.uses_mipmaps());
+    }
+}
@@ -136,16 +136,17 @@ impl Matrix4 {
             0.0, 0.0, 0.0, 1.0,
         )
     }
+    //T * R * S, i.e. scale first, then rotate, then translate
     pub fn from_trs_mut(&mut self, translation:&Vector3, rotation:&Quaternion, scale:&Vector3) {
-        self.set_from_scale(scale);
+        self.copy_from(&Self::from_translation(translation));
         self.mul_mut(&Self::from_rotation(rotation));
-        self.mul_mut(&Self::from_translation(translation));
+        self.mul_mut(&Self::from_scale(scale));
     }
 
     pub fn from_trs(translation:&Vector3, rotation:&Quaternion, scale:&Vector3) -> Self {
-        let mut _self = Self::from_scale(scale);
+        let mut _self = Self::from_translation(translation);
         _self.mul_mut(&Self::from_rotation(rotation));
-        _self.mul_mut(&Self::from_translation(translation));
+        _self.mul_mut(&Self::from_scale(scale));
         _self
     }
 
@@ -270,10 +271,14 @@ impl std::ops::Mul<Matrix4> for Matrix4 {
 }
 
 pub trait TransformValues: AsRef<[f64]> + AsMut<[f64]> + Default {
-    fn len(self: &Self) -> usize;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
     //TODO: cache! maybe Cow?
-    fn to_vec_f32(self: &Self) -> Vec<f32> {
+    fn to_vec_f32(&self) -> Vec<f32> {
         self.as_ref().iter().map(|n| *n as f32).collect()
     }
 
@@ -322,3 +327,62 @@ macro_rules! impl_asref {
 }
 
 impl_asref!{Vector3, Quaternion, Matrix4}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    //column-major, so the translation is in the last column
+    fn transform_point(matrix:&Matrix4, point:[f64;3]) -> [f64;3] {
+        let m:&[f64] = matrix.as_ref();
+        let [x, y, z] = point;
+        [
+            (m[0] * x) + (m[4] * y) + (m[8] * z) + m[12],
+            (m[1] * x) + (m[5] * y) + (m[9] * z) + m[13],
+            (m[2] * x) + (m[6] * y) + (m[10] * z) + m[14],
+        ]
+    }
+
+    fn assert_near(actual:&[f64], expected:&[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn get_trs() -> (Vector3, Quaternion, Vector3) {
+        (
+            Vector3::new(1.0, 2.0, 3.0),
+            //90 degrees around z
+            Quaternion::new(0.0, 0.0, FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            Vector3::new(2.0, 1.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn trs_scales_then_rotates_then_translates() {
+        let (translation, rotation, scale) = get_trs();
+        let matrix = Matrix4::from_trs(&translation, &rotation, &scale);
+
+        //(1,0,0) -> scaled (2,0,0) -> rotated (0,2,0) -> translated (1,4,3)
+        assert_near(&transform_point(&matrix, [1.0, 0.0, 0.0]), &[1.0, 4.0, 3.0]);
+        //(0,1,0) -> scaled (0,1,0) -> rotated (-1,0,0) -> translated (0,2,3)
+        assert_near(&transform_point(&matrix, [0.0, 1.0, 0.0]), &[0.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn trs_matches_product() {
+        let (translation, rotation, scale) = get_trs();
+        let expected = Matrix4::from_translation(&translation)
+            * Matrix4::from_rotation(&rotation)
+            * Matrix4::from_scale(&scale);
+
+        let matrix = Matrix4::from_trs(&translation, &rotation, &scale);
+        assert_near(matrix.as_ref(), expected.as_ref());
+
+        let mut matrix = Matrix4::default();
+        matrix.from_trs_mut(&translation, &rotation, &scale);
+        assert_near(matrix.as_ref(), expected.as_ref());
+    }
+}